@@ -0,0 +1,22 @@
+#version 430 core
+
+// One direction of a separable 9-tap Gaussian blur, using linear filtering to read two texels
+// per sample
+in vec2 uv;
+out vec4 color;
+
+layout (binding = 3) uniform sampler2D image;
+uniform vec2 direction; // One texel along the axis to blur
+
+const float offsets[3] = float[](0.0, 1.3846153846, 3.2307692308);
+const float weights[3] = float[](0.2270270270, 0.3162162162, 0.0702702703);
+
+void main()
+{
+    vec3 sum = texture(image, uv).rgb * weights[0];
+    for (int i = 1; i < 3; i++) {
+        sum += texture(image, uv + direction * offsets[i]).rgb * weights[i];
+        sum += texture(image, uv - direction * offsets[i]).rgb * weights[i];
+    }
+    color = vec4(sum, 1.0);
+}
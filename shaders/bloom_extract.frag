@@ -0,0 +1,17 @@
+#version 430 core
+
+// Keeps what is brighter than the threshold, drawn at half the resolution of the scene. Sampling
+// between four texels with linear filtering averages them.
+in vec2 uv;
+out vec4 color;
+
+layout (binding = 2) uniform sampler2D scene;
+uniform float threshold = 1.0;
+
+void main()
+{
+    vec3 hdr = texture(scene, uv).rgb;
+    float brightness = max(hdr.r, max(hdr.g, hdr.b));
+    float contribution = max(brightness - threshold, 0.0) / max(brightness, 0.0001);
+    color = vec4(hdr * contribution, 1.0);
+}
@@ -0,0 +1,33 @@
+#version 430 core
+
+// Adds the bloom to the scene and brings it from HDR into the displayable range
+in vec2 uv;
+out vec4 color;
+
+layout (binding = 2) uniform sampler2D scene;
+layout (binding = 3) uniform sampler2D bloom;
+uniform float bloomStrength = 0.0; // Zero without bloom
+uniform float exposure = 1.0;
+uniform bool tonemap = true;
+uniform bool gammaCorrect = true;
+
+// Fit of the ACES filmic curve by Krzysztof Narkowicz
+vec3 aces(vec3 x)
+{
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0.0, 1.0);
+}
+
+void main()
+{
+    vec3 hdr = texture(scene, uv).rgb;
+    if (bloomStrength > 0.0) {
+        hdr += bloomStrength * texture(bloom, uv).rgb;
+    }
+    hdr *= exposure;
+
+    vec3 ldr = tonemap ? aces(hdr) : clamp(hdr, 0.0, 1.0);
+    if (gammaCorrect) {
+        ldr = pow(ldr, vec3(1.0 / 2.2));
+    }
+    color = vec4(ldr, 1.0);
+}
@@ -0,0 +1,14 @@
+#version 430 core
+
+layout (location = 0) in vec3 position; // In world space
+layout (location = 1) in vec4 color;
+
+#include "frame.glsl"
+
+out vec4 lineColor;
+
+void main()
+{
+    lineColor = color;
+    gl_Position = viewProjection * vec4(position, 1.0);
+}
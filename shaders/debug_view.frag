@@ -0,0 +1,26 @@
+#version 430 core
+
+#include "frame.glsl"
+
+// Shows what goes into the shading instead of the shaded result, drawn with simple.vert. The
+// colors are meant for the display as they are, the post-processing leaves them alone.
+in vec4 vertexColor;
+in vec3 vertexNormals;
+in vec3 worldPosition;
+out vec4 color;
+
+#define SHOW_NORMALS 1
+#define SHOW_DEPTH 2
+
+uniform int mode = SHOW_NORMALS;
+uniform float depthRange = 250.0; // Distance from the camera that shows as black
+
+void main()
+{
+    if (mode == SHOW_NORMALS) {
+        color = vec4(normalize(vertexNormals) * 0.5 + 0.5, 1.0);
+    } else {
+        float distance = length(worldPosition - cameraPosition.xyz);
+        color = vec4(vec3(1.0 - clamp(distance / depthRange, 0.0, 1.0)), 1.0);
+    }
+}
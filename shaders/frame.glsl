@@ -0,0 +1,30 @@
+// Per-frame data, updated once per frame by FrameUniforms in src/frame_uniforms.rs. Keep the
+// layout in sync with FrameData there.
+
+// The size of the light array is defined by the program, see frame_uniforms::shader_builder
+#ifndef MAX_LIGHTS
+#error MAX_LIGHTS is not defined, build the shader with frame_uniforms::shader_builder
+#endif
+
+#define DIRECTIONAL_LIGHT 0
+#define POINT_LIGHT 1
+#define SPOT_LIGHT 2
+
+struct Light {
+    vec4 position;  // w is the kind of light
+    vec4 color;     // rgb times intensity, w is the range of point and spot lights
+    vec4 direction; // Where directional and spot lights point
+    vec4 cone;      // Cosines of the inner and outer angles of spot lights
+};
+
+layout (std140, binding = 0) uniform Frame {
+    mat4 view;
+    mat4 projection;
+    mat4 viewProjection;
+    mat4 lightMatrix;    // World space to the clip space of the light casting shadows
+    vec4 cameraPosition;
+    float time;
+    int lightCount;
+    int shadowLight;     // Index of the light casting shadows, -1 for none
+    Light lights[MAX_LIGHTS];
+};
@@ -0,0 +1,136 @@
+#version 430 core
+
+// Fast approximate anti-aliasing, after FXAA 3.11 by Timothy Lottes. Finds edges from the
+// contrast in luma, walks along them to their ends and blends across them depending on where
+// along the edge the pixel sits.
+in vec2 uv;
+out vec4 color;
+
+layout (binding = 2) uniform sampler2D image; // Tonemapped and gamma corrected
+uniform vec2 texelSize;
+
+#define EDGE_THRESHOLD_MIN 0.0312
+#define EDGE_THRESHOLD_MAX 0.125
+#define SUBPIXEL_QUALITY 0.75
+#define ITERATIONS 12
+
+float luma(vec2 at)
+{
+    return dot(texture(image, at).rgb, vec3(0.299, 0.587, 0.114));
+}
+
+float lumaOffset(ivec2 offset)
+{
+    return luma(uv + vec2(offset) * texelSize);
+}
+
+void main()
+{
+    vec3 center = texture(image, uv).rgb;
+    float lumaCenter = dot(center, vec3(0.299, 0.587, 0.114));
+    float lumaDown = lumaOffset(ivec2(0, -1));
+    float lumaUp = lumaOffset(ivec2(0, 1));
+    float lumaLeft = lumaOffset(ivec2(-1, 0));
+    float lumaRight = lumaOffset(ivec2(1, 0));
+
+    float lumaMin = min(lumaCenter, min(min(lumaDown, lumaUp), min(lumaLeft, lumaRight)));
+    float lumaMax = max(lumaCenter, max(max(lumaDown, lumaUp), max(lumaLeft, lumaRight)));
+    float lumaRange = lumaMax - lumaMin;
+    if (lumaRange < max(EDGE_THRESHOLD_MIN, lumaMax * EDGE_THRESHOLD_MAX)) {
+        color = vec4(center, 1.0); // Not on an edge
+        return;
+    }
+
+    float lumaDownLeft = lumaOffset(ivec2(-1, -1));
+    float lumaUpRight = lumaOffset(ivec2(1, 1));
+    float lumaUpLeft = lumaOffset(ivec2(-1, 1));
+    float lumaDownRight = lumaOffset(ivec2(1, -1));
+
+    float lumaDownUp = lumaDown + lumaUp;
+    float lumaLeftRight = lumaLeft + lumaRight;
+    float lumaLeftCorners = lumaDownLeft + lumaUpLeft;
+    float lumaDownCorners = lumaDownLeft + lumaDownRight;
+    float lumaRightCorners = lumaDownRight + lumaUpRight;
+    float lumaUpCorners = lumaUpRight + lumaUpLeft;
+
+    // Whether the edge runs horizontally or vertically
+    float edgeHorizontal = abs(-2.0 * lumaLeft + lumaLeftCorners)
+        + abs(-2.0 * lumaCenter + lumaDownUp) * 2.0
+        + abs(-2.0 * lumaRight + lumaRightCorners);
+    float edgeVertical = abs(-2.0 * lumaUp + lumaUpCorners)
+        + abs(-2.0 * lumaCenter + lumaLeftRight) * 2.0
+        + abs(-2.0 * lumaDown + lumaDownCorners);
+    bool isHorizontal = edgeHorizontal >= edgeVertical;
+
+    // Which side of the pixel the edge is on
+    float luma1 = isHorizontal ? lumaDown : lumaLeft;
+    float luma2 = isHorizontal ? lumaUp : lumaRight;
+    float gradient1 = luma1 - lumaCenter;
+    float gradient2 = luma2 - lumaCenter;
+    bool is1Steepest = abs(gradient1) >= abs(gradient2);
+    float gradientScaled = 0.25 * max(abs(gradient1), abs(gradient2));
+
+    float stepLength = isHorizontal ? texelSize.y : texelSize.x;
+    float lumaLocalAverage;
+    if (is1Steepest) {
+        stepLength = -stepLength;
+        lumaLocalAverage = 0.5 * (luma1 + lumaCenter);
+    } else {
+        lumaLocalAverage = 0.5 * (luma2 + lumaCenter);
+    }
+
+    // Walk along the edge in both directions until its ends
+    vec2 edgeUv = uv;
+    if (isHorizontal) {
+        edgeUv.y += stepLength * 0.5;
+    } else {
+        edgeUv.x += stepLength * 0.5;
+    }
+    vec2 offset = isHorizontal ? vec2(texelSize.x, 0.0) : vec2(0.0, texelSize.y);
+    vec2 uv1 = edgeUv - offset;
+    vec2 uv2 = edgeUv + offset;
+    float lumaEnd1 = luma(uv1) - lumaLocalAverage;
+    float lumaEnd2 = luma(uv2) - lumaLocalAverage;
+    bool reached1 = abs(lumaEnd1) >= gradientScaled;
+    bool reached2 = abs(lumaEnd2) >= gradientScaled;
+    for (int i = 0; i < ITERATIONS && !(reached1 && reached2); i++) {
+        float stride = i < 4 ? 1.0 : 2.0;
+        if (!reached1) {
+            uv1 -= offset * stride;
+            lumaEnd1 = luma(uv1) - lumaLocalAverage;
+            reached1 = abs(lumaEnd1) >= gradientScaled;
+        }
+        if (!reached2) {
+            uv2 += offset * stride;
+            lumaEnd2 = luma(uv2) - lumaLocalAverage;
+            reached2 = abs(lumaEnd2) >= gradientScaled;
+        }
+    }
+
+    float distance1 = isHorizontal ? uv.x - uv1.x : uv.y - uv1.y;
+    float distance2 = isHorizontal ? uv2.x - uv.x : uv2.y - uv.y;
+    bool isDirection1 = distance1 < distance2;
+    float distanceFinal = min(distance1, distance2);
+    float edgeLength = distance1 + distance2;
+    float pixelOffset = -distanceFinal / edgeLength + 0.5;
+
+    // Only blend if the end of the edge closest to us varies like the center does
+    bool isLumaCenterSmaller = lumaCenter < lumaLocalAverage;
+    bool correctVariation = ((isDirection1 ? lumaEnd1 : lumaEnd2) < 0.0) != isLumaCenterSmaller;
+    float finalOffset = correctVariation ? pixelOffset : 0.0;
+
+    // Blend single pixels standing out from their surroundings
+    float lumaAverage = (1.0 / 12.0) * (2.0 * (lumaDownUp + lumaLeftRight) + lumaLeftCorners + lumaRightCorners);
+    float subPixelOffset1 = clamp(abs(lumaAverage - lumaCenter) / lumaRange, 0.0, 1.0);
+    float subPixelOffset2 = (-2.0 * subPixelOffset1 + 3.0) * subPixelOffset1 * subPixelOffset1;
+    float subPixelOffsetFinal = subPixelOffset2 * subPixelOffset2 * SUBPIXEL_QUALITY;
+    finalOffset = max(finalOffset, subPixelOffsetFinal);
+
+    vec2 finalUv = uv;
+    if (isHorizontal) {
+        finalUv.y += finalOffset * stepLength;
+    } else {
+        finalUv.x += finalOffset * stepLength;
+    }
+    color = vec4(texture(image, finalUv).rgb, 1.0);
+}
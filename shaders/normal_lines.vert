@@ -0,0 +1,15 @@
+#version 430 core
+
+layout (location = 0) in vec3 position;
+layout (location = 2) in vec3 normals;
+
+uniform mat4 modelMatrix;
+
+out vec3 worldNormal;
+
+// Stays in world space, the geometry shader projects the lines
+void main()
+{
+    gl_Position = modelMatrix * vec4(position, 1.0);
+    worldNormal = normalize(transpose(inverse(mat3(modelMatrix))) * normals);
+}
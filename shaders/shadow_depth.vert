@@ -0,0 +1,13 @@
+#version 430 core
+
+layout (location = 0) in vec3 position;
+
+#include "frame.glsl"
+
+uniform mat4 modelMatrix;
+
+// Only the depth as seen from the light is needed
+void main()
+{
+    gl_Position = lightMatrix * modelMatrix * vec4(position, 1.0f);
+}
@@ -1,16 +1,88 @@
 #version 430 core
 
+#include "frame.glsl"
+
 in vec4 vertexColor;
 in vec3 vertexNormals;
+in vec3 worldPosition;
 out vec4 color;
 
+// Material
+uniform float ambientStrength = 0.08;
+uniform float specularStrength = 0.4;
+uniform float shininess = 32.0;
+
+// Shadows from light `shadowLight`, see src/shadow.rs
+layout (binding = 1) uniform sampler2DShadow shadowMap;
+uniform float shadowBias = 0.002;
+uniform int shadowPcfRadius = 1;
+
+// Fades point and spot lights out smoothly, reaching zero at their range
+float attenuation(float distance, float range)
+{
+    float fade = clamp(1.0 - pow(distance / range, 4.0), 0.0, 1.0);
+    return fade * fade / (1.0 + distance * distance);
+}
 
+// Fraction of the light reaching the fragment, averaged over the texels around it
+float shadow(vec3 normal, vec3 toLight)
+{
+    // From the clip space of the light in [-1, 1] to texture coordinates and depth in [0, 1]
+    vec3 shadowPosition = (lightMatrix * vec4(worldPosition, 1.0)).xyz * 0.5 + 0.5;
+    if (shadowPosition.z > 1.0) {
+        return 1.0; // Beyond the far plane of the light
+    }
+    // Surfaces at a grazing angle to the light need a larger offset
+    float bias = shadowBias * (1.0 + 4.0 * (1.0 - max(0.0, dot(normal, toLight))));
+    vec2 texelSize = 1.0 / vec2(textureSize(shadowMap, 0));
+    float lit = 0.0;
+    for (int x = -shadowPcfRadius; x <= shadowPcfRadius; x++) {
+        for (int y = -shadowPcfRadius; y <= shadowPcfRadius; y++) {
+            vec2 offset = vec2(x, y) * texelSize;
+            lit += texture(shadowMap, vec3(shadowPosition.xy + offset, shadowPosition.z - bias));
+        }
+    }
+    float side = 2.0 * shadowPcfRadius + 1.0;
+    return lit / (side * side);
+}
 
 void main()
 {
-    vec3 lightDirection = normalize(vec3(0.8, -0.5, 0.6));
+    // Vertex colors are meant for the display, light adds up in linear space
+    vec3 baseColor = pow(vertexColor.rgb, vec3(2.2));
+    vec3 normal = normalize(vertexNormals);
+    vec3 toCamera = normalize(cameraPosition.xyz - worldPosition);
+
+    vec3 result = ambientStrength * baseColor;
+    for (int i = 0; i < lightCount; i++) {
+        Light light = lights[i];
+        int kind = int(light.position.w);
+
+        vec3 toLight;
+        float strength = 1.0;
+        if (kind == DIRECTIONAL_LIGHT) {
+            toLight = -light.direction.xyz;
+            if (i == shadowLight) {
+                strength = shadow(normal, toLight);
+            }
+        } else {
+            vec3 offset = light.position.xyz - worldPosition;
+            float distance = length(offset);
+            toLight = offset / distance;
+            strength = attenuation(distance, light.color.w);
+            if (kind == SPOT_LIGHT) {
+                float cosAngle = dot(-toLight, light.direction.xyz);
+                strength *= smoothstep(light.cone.y, light.cone.x, cosAngle);
+            }
+        }
+
+        // Blinn-Phong
+        float diffuse = max(0.0, dot(normal, toLight));
+        vec3 halfway = normalize(toLight + toCamera);
+        float specular = diffuse > 0.0 ? pow(max(0.0, dot(normal, halfway)), shininess) : 0.0;
 
-    float diffuse = max(0.0,dot(vertexNormals, -lightDirection));
+        result += strength * light.color.rgb * (diffuse * baseColor + specularStrength * specular);
+    }
 
-    color = vec4(vec3(diffuse), 1.0);
-}
\ No newline at end of file
+    color = vec4(result, vertexColor.a);
+}
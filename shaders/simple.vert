@@ -4,10 +4,12 @@ layout (location = 0) in vec3 position;
 layout (location = 1) in vec4 color;
 layout (location = 2) in vec3 normals;
 
+#include "frame.glsl"
+
 out vec4 vertexColor;
 out vec3 vertexNormals;
+out vec3 worldPosition;
 
-uniform mat4 modelViewMatrix;
 uniform mat4 modelMatrix;
 
 
@@ -15,10 +17,13 @@ void main()
 {
 
 
-    gl_Position = modelViewMatrix*vec4(position, 1.0f);
+    vec4 world = modelMatrix * vec4(position, 1.0f);
+    worldPosition = world.xyz;
+    gl_Position = viewProjection * world;
 
-    mat3 modelMatrix3x3 = mat3(modelMatrix);
-    vertexNormals = normalize(modelMatrix3x3 * normals);
+    // The inverse transpose keeps normals perpendicular to the surface under non-uniform scale
+    mat3 normalMatrix = transpose(inverse(mat3(modelMatrix)));
+    vertexNormals = normalize(normalMatrix * normals);
 
     vertexColor = color;
 }
\ No newline at end of file
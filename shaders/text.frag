@@ -0,0 +1,12 @@
+#version 430 core
+
+in vec2 uv;
+in vec4 textColor;
+out vec4 color;
+
+layout (binding = 4) uniform sampler2D font; // Coverage of the glyphs in red
+
+void main()
+{
+    color = vec4(textColor.rgb, textColor.a * texture(font, uv).r);
+}
@@ -0,0 +1,18 @@
+#version 430 core
+
+layout (location = 0) in vec2 position; // In pixels from the top left corner
+layout (location = 1) in vec2 texCoords;
+layout (location = 2) in vec4 color;
+
+uniform vec2 screenSize;
+
+out vec2 uv;
+out vec4 textColor;
+
+void main()
+{
+    uv = texCoords;
+    textColor = color;
+    vec2 ndc = position / screenSize * 2.0 - 1.0;
+    gl_Position = vec4(ndc.x, -ndc.y, 0.0, 1.0);
+}
@@ -0,0 +1,11 @@
+#version 430 core
+
+// The edges of the triangles, drawn with simple.vert over the shaded scene
+out vec4 color;
+
+uniform vec4 wireColor = vec4(0.1, 1.0, 0.3, 1.0);
+
+void main()
+{
+    color = wireColor;
+}
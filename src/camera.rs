@@ -0,0 +1,33 @@
+// The free camera steered with the keyboard
+
+pub struct Camera {
+    pub translation : glm::Vec3, // Applied to the world before rotating, the opposite of where the camera is
+    pub rotation    : glm::Vec2, // Pitch around X, then yaw around Y, in radians
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Camera {
+            translation: glm::vec3(0.0, 0.0, -10.0),
+            rotation: glm::vec2(0.0, 0.0),
+        }
+    }
+}
+
+impl Camera {
+    pub fn view(&self) -> glm::Mat4 {
+        let rotate_x = glm::rotate_x(&glm::Mat4::identity(), self.rotation.x);
+        let rotate_y = glm::rotate_y(&glm::Mat4::identity(), self.rotation.y);
+        let translate = glm::translation(&self.translation);
+        rotate_x * rotate_y * translate
+    }
+
+    // Where the camera is in the world
+    pub fn position(&self) -> glm::Vec3 {
+        -self.translation
+    }
+
+    pub fn projection(&self, aspect_ratio: f32) -> glm::Mat4 {
+        glm::perspective(aspect_ratio, 0.5, 1.0, 1000.0)
+    }
+}
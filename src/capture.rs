@@ -0,0 +1,70 @@
+// Reading back what was drawn and saving it to PNG files, one at a time or as a numbered sequence
+
+use std::path::{Path, PathBuf};
+
+// The picture in `framebuffer`, 0 being the window, top row first. Call before swapping buffers.
+pub unsafe fn read_pixels(framebuffer: u32, width: i32, height: i32) -> image::RgbImage {
+    let mut pixels = vec![0u8; width as usize * height as usize * 3];
+    gl::BindFramebuffer(gl::READ_FRAMEBUFFER, framebuffer);
+    gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+    gl::ReadPixels(
+        0,
+        0,
+        width,
+        height,
+        gl::RGB,
+        gl::UNSIGNED_BYTE,
+        pixels.as_mut_ptr() as *mut std::ffi::c_void,
+    );
+    gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+    let image = image::RgbImage::from_raw(width as u32, height as u32, pixels)
+        .expect("Pixel buffer has the wrong size");
+    // OpenGL starts at the bottom row
+    image::imageops::flip_vertical(&image)
+}
+
+pub fn save_png(image: &image::RgbImage, path: &Path) -> Result<(), String> {
+    image.save_with_format(path, image::ImageFormat::Png)
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+// A file in captures/ for a screenshot taken at `time`, numbered so that it does not overwrite
+// an earlier one taken at the same time
+pub fn screenshot_path(time: f32) -> PathBuf {
+    (0..)
+        .map(|n| match n {
+            0 => PathBuf::from(format!("captures/screenshot_{:.2}s.png", time)),
+            n => PathBuf::from(format!("captures/screenshot_{:.2}s_{}.png", time, n)),
+        })
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+// Frames saved one after the other as frame_0000.png, frame_0001.png... in a directory. The
+// simulation advances by `step` seconds per frame while recording, however long saving takes,
+// so that the sequence plays back at the right speed.
+pub struct Recording {
+    pub directory : PathBuf,
+    pub step      : f32,
+    frame         : u32,
+}
+
+impl Recording {
+    pub fn start(directory: PathBuf, fps: f32) -> Result<Self, String> {
+        std::fs::create_dir_all(&directory)
+            .map_err(|e| format!("Failed to create {}: {}", directory.display(), e))?;
+        Ok(Recording { directory, step: 1.0 / fps, frame: 0 })
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frame
+    }
+
+    // Saves the next frame from `framebuffer`
+    pub unsafe fn capture(&mut self, framebuffer: u32, width: i32, height: i32) -> Result<(), String> {
+        let path = self.directory.join(format!("frame_{:04}.png", self.frame));
+        save_png(&read_pixels(framebuffer, width, height), &path)?;
+        self.frame += 1;
+        Ok(())
+    }
+}
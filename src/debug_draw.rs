@@ -0,0 +1,183 @@
+// Immediate mode drawing of lines and shapes for debugging
+//
+// Shapes are queued from anywhere during the frame, in world space, then drawn as lines in one
+// go after the scene and forgotten. Queue them again every frame to keep them on screen.
+
+use crate::frame_uniforms;
+use crate::gpu::{Buffer, VertexArray};
+use crate::scene_graph::SceneNode;
+use crate::shader::Shader;
+use crate::util::{offset, size_of};
+
+// Segments in circles of spheres
+const CIRCLE_SEGMENTS: usize = 24;
+
+pub const RED: [f32; 4] = [1.0, 0.2, 0.2, 1.0];
+pub const GREEN: [f32; 4] = [0.2, 1.0, 0.2, 1.0];
+pub const BLUE: [f32; 4] = [0.3, 0.4, 1.0, 1.0];
+pub const YELLOW: [f32; 4] = [1.0, 0.9, 0.2, 1.0];
+pub const CYAN: [f32; 4] = [0.2, 0.9, 1.0, 1.0];
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct DebugVertex {
+    position : [f32; 3],
+    color    : [f32; 4],
+}
+
+pub struct DebugDraw {
+    pub show_scene : bool, // Draw the pivot and axes of every scene node, and the flight paths
+    pub xray       : bool, // Draw over the scene instead of being hidden behind it
+    vertices       : Vec<DebugVertex>, // Pairs making up lines
+    vao            : VertexArray,
+    buffer         : Buffer,
+    shader         : Shader,
+}
+
+impl DebugDraw {
+    pub unsafe fn new() -> Self {
+        let vao = VertexArray::new();
+        vao.bind();
+        let buffer = Buffer::new(gl::ARRAY_BUFFER);
+        buffer.bind();
+        let stride = size_of::<DebugVertex>();
+        gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, stride, offset::<f32>(0));
+        gl::EnableVertexAttribArray(0);
+        gl::VertexAttribPointer(1, 4, gl::FLOAT, gl::FALSE, stride, offset::<f32>(3));
+        gl::EnableVertexAttribArray(1);
+        gl::BindVertexArray(0);
+
+        let shader = frame_uniforms::shader_builder()
+            .attach("debug_lines.frag")
+            .attach("debug_lines.vert")
+            .link()
+            .unwrap_or_else(|log| panic!("{}", log));
+
+        DebugDraw {
+            show_scene: false,
+            xray: false,
+            vertices: vec![],
+            vao,
+            buffer,
+            shader,
+        }
+    }
+
+    pub unsafe fn reload_shader(&mut self) {
+        self.shader.reload_if_changed();
+    }
+
+    pub fn line(&mut self, from: &glm::Vec3, to: &glm::Vec3, color: [f32; 4]) {
+        self.vertices.push(DebugVertex { position: (*from).into(), color });
+        self.vertices.push(DebugVertex { position: (*to).into(), color });
+    }
+
+    // Lines through consecutive points
+    pub fn path(&mut self, points: &[glm::Vec3], color: [f32; 4]) {
+        for pair in points.windows(2) {
+            self.line(&pair[0], &pair[1], color);
+        }
+    }
+
+    // The box from `min` to `max`, placed by `transform`
+    #[allow(dead_code)]
+    pub fn cuboid(&mut self, transform: &glm::Mat4, min: &glm::Vec3, max: &glm::Vec3, color: [f32; 4]) {
+        let corner = |i: usize| {
+            let local = glm::vec3(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            );
+            (transform * local.push(1.0)).xyz()
+        };
+        // Corners differing in one bit share an edge
+        for i in 0..8 {
+            for bit in [1, 2, 4] {
+                if i & bit == 0 {
+                    self.line(&corner(i), &corner(i | bit), color);
+                }
+            }
+        }
+    }
+
+    // Three circles around the axes
+    pub fn sphere(&mut self, center: &glm::Vec3, radius: f32, color: [f32; 4]) {
+        let angle = |i: usize| i as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU;
+        for axis in 0..3 {
+            let point = |i: usize| {
+                let (sin, cos) = angle(i).sin_cos();
+                let mut offset = glm::Vec3::zeros();
+                offset[(axis + 1) % 3] = cos * radius;
+                offset[(axis + 2) % 3] = sin * radius;
+                center + offset
+            };
+            for i in 0..CIRCLE_SEGMENTS {
+                self.line(&point(i), &point(i + 1), color);
+            }
+        }
+    }
+
+    // The X, Y and Z axes of `transform` in red, green and blue, `size` units long
+    pub fn axes(&mut self, transform: &glm::Mat4, size: f32) {
+        let origin = (transform * glm::vec4(0.0, 0.0, 0.0, 1.0)).xyz();
+        for (i, color) in [RED, GREEN, BLUE].iter().enumerate() {
+            let direction = transform.column(i).xyz();
+            if direction.norm() > 0.0 {
+                self.line(&origin, &(origin + direction.normalize() * size), *color);
+            }
+        }
+    }
+
+    // A line with a head of four short lines at `to`
+    pub fn arrow(&mut self, from: &glm::Vec3, to: &glm::Vec3, color: [f32; 4]) {
+        self.line(from, to, color);
+        let along = to - from;
+        let length = along.norm();
+        if length == 0.0 {
+            return;
+        }
+        let direction = along / length;
+        let helper = if direction.y.abs() < 0.9 { glm::vec3(0.0, 1.0, 0.0) } else { glm::vec3(1.0, 0.0, 0.0) };
+        let side = glm::normalize(&glm::cross(&direction, &helper));
+        let up = glm::cross(&side, &direction);
+        let head = length * 0.15;
+        let base = to - direction * head;
+        for spread in [side, -side, up, -up] {
+            self.line(to, &(base + spread * head * 0.5), color);
+        }
+    }
+
+    // The pivot and axes of `node` and its descendants
+    pub fn scene_nodes(&mut self, node: &SceneNode, transformation_so_far: &glm::Mat4) {
+        let current_transform = transformation_so_far * node.local_transform();
+        // The reference point stays in place under the rotation and scaling of the node
+        let pivot = (current_transform * node.reference_point.push(1.0)).xyz();
+        let mut at_pivot = current_transform;
+        at_pivot.set_column(3, &pivot.push(1.0));
+        self.axes(&at_pivot, 2.0);
+        self.sphere(&pivot, 0.3, YELLOW);
+
+        for &child in &node.children {
+            if !child.is_null() {
+                self.scene_nodes(unsafe { &*child }, &current_transform);
+            }
+        }
+    }
+
+    // Draws everything queued since the last call, using the camera of the Frame uniform block
+    pub unsafe fn flush(&mut self) {
+        if self.vertices.is_empty() {
+            return;
+        }
+        self.vao.bind();
+        self.buffer.bind();
+        self.buffer.set_data(&self.vertices, gl::STREAM_DRAW);
+        self.shader.activate();
+        if self.xray {
+            gl::Disable(gl::DEPTH_TEST);
+        }
+        gl::DrawArrays(gl::LINES, 0, self.vertices.len() as i32);
+        gl::Enable(gl::DEPTH_TEST);
+        self.vertices.clear();
+    }
+}
@@ -0,0 +1,52 @@
+// Shader sources compiled into the binary, so that it runs from any directory
+//
+// Sources are registered under a logical name, their path relative to the shaders directory. A
+// file at that path in SHADER_DIR overrides the embedded version, which lets shaders be edited
+// and hot reloaded without rebuilding when running from the repository.
+
+use std::path::{Path, PathBuf};
+
+// Where overrides are looked for, relative to the working directory
+pub const SHADER_DIR: &str = "shaders";
+
+const EMBEDDED: &[(&str, &str)] = &[
+    ("bloom_blur.frag", include_str!("../shaders/bloom_blur.frag")),
+    ("bloom_extract.frag", include_str!("../shaders/bloom_extract.frag")),
+    ("composite.frag", include_str!("../shaders/composite.frag")),
+    ("debug_lines.frag", include_str!("../shaders/debug_lines.frag")),
+    ("debug_lines.vert", include_str!("../shaders/debug_lines.vert")),
+    ("debug_view.frag", include_str!("../shaders/debug_view.frag")),
+    ("frame.glsl", include_str!("../shaders/frame.glsl")),
+    ("fullscreen.vert", include_str!("../shaders/fullscreen.vert")),
+    ("fxaa.frag", include_str!("../shaders/fxaa.frag")),
+    ("normal_lines.geom", include_str!("../shaders/normal_lines.geom")),
+    ("normal_lines.vert", include_str!("../shaders/normal_lines.vert")),
+    ("shadow_depth.frag", include_str!("../shaders/shadow_depth.frag")),
+    ("shadow_depth.vert", include_str!("../shaders/shadow_depth.vert")),
+    ("simple.frag", include_str!("../shaders/simple.frag")),
+    ("simple.vert", include_str!("../shaders/simple.vert")),
+    ("text.frag", include_str!("../shaders/text.frag")),
+    ("text.vert", include_str!("../shaders/text.vert")),
+    ("wireframe.frag", include_str!("../shaders/wireframe.frag")),
+];
+
+pub fn get(name: &str) -> Option<&'static str> {
+    EMBEDDED.iter().find(|(n, _)| *n == name).map(|(_, source)| *source)
+}
+
+// The path of the override for `name`, whether it exists or not
+pub fn override_path(name: &str) -> PathBuf {
+    Path::new(SHADER_DIR).join(name)
+}
+
+// Reads the file at `path`, falling back to the embedded source if it is missing and inside
+// SHADER_DIR
+pub fn read(path: &Path) -> Result<String, String> {
+    match std::fs::read_to_string(path) {
+        Ok(source) => Ok(source),
+        Err(e) => path.strip_prefix(SHADER_DIR).ok()
+            .and_then(|name| get(&name.to_string_lossy().replace('\\', "/")))
+            .map(str::to_owned)
+            .ok_or_else(|| format!("Failed to read shader source {}: {}", path.display(), e)),
+    }
+}
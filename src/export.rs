@@ -0,0 +1,315 @@
+// Export of meshes to OBJ and of the scene graph to glTF 2.0, for opening a frame in e.g. Blender
+//
+// Scene nodes only hold the uploaded meshes, so the CPU side meshes are looked up by the address
+// of the GpuMesh, which nodes sharing a mesh have in common. Nodes are exported with their full
+// resolution geometry, levels of detail are left out.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use serde_json::{json, Value};
+
+use crate::gpu::GpuMesh;
+use crate::mesh::Mesh;
+use crate::scene_graph::SceneNode;
+
+// glTF constants
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Transforms {
+    Hierarchical, // Keep the node tree, with a matrix per node
+    Baked,        // A flat list of nodes with the world transformation applied to their vertices
+}
+
+// Writes positions, normals and faces. Vertex colors are written after the positions, which
+// Blender and MeshLab read.
+pub fn export_obj(mesh: &Mesh, path: &str) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "# Exported from gloom-rs")?;
+    for (i, p) in mesh.vertices.chunks_exact(3).enumerate() {
+        match mesh.colors.get(i * 4..i * 4 + 3) {
+            Some(c) => writeln!(out, "v {} {} {} {} {} {}", p[0], p[1], p[2], c[0], c[1], c[2])?,
+            None => writeln!(out, "v {} {} {}", p[0], p[1], p[2])?,
+        }
+    }
+    for n in mesh.normals.chunks_exact(3) {
+        writeln!(out, "vn {} {} {}", n[0], n[1], n[2])?;
+    }
+    let has_normals = mesh.normals.len() == mesh.vertices.len();
+    for tri in mesh.indices.chunks_exact(3) {
+        // OBJ indices start at 1
+        let [a, b, c] = [tri[0] + 1, tri[1] + 1, tri[2] + 1];
+        if has_normals {
+            writeln!(out, "f {a}//{a} {b}//{b} {c}//{c}", a = a, b = b, c = c)?;
+        } else {
+            writeln!(out, "f {} {} {}", a, b, c)?;
+        }
+    }
+    out.flush()
+}
+
+// Writes the scene below `root` as it is posed right now. The file is binary glTF (.glb) if
+// `path` ends with .glb, otherwise JSON with the vertex data in a .bin file next to it.
+pub fn export_gltf(
+    root: &SceneNode,
+    meshes: &HashMap<*const GpuMesh, &Mesh>,
+    transforms: Transforms,
+    path: &str,
+) -> io::Result<()> {
+    let mut writer = GltfWriter::default();
+    let roots = match transforms {
+        Transforms::Hierarchical => vec![writer.push_node_tree(root, meshes)],
+        Transforms::Baked => {
+            let mut roots = vec![];
+            writer.push_baked_nodes(root, &glm::Mat4::identity(), meshes, &mut roots);
+            roots
+        }
+    };
+
+    let path = Path::new(path);
+    let glb = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("glb"));
+
+    // glTF does not allow empty arrays or buffers, so what a scene without geometry lacks is left
+    // out altogether
+    let mut document = json!({ "asset": { "version": "2.0", "generator": "gloom-rs" } });
+    if !roots.is_empty() {
+        document["scene"] = json!(0);
+        document["scenes"] = json!([{ "nodes": roots }]);
+    }
+    let arrays = [
+        ("nodes", writer.nodes),
+        ("meshes", writer.meshes),
+        ("accessors", writer.accessors),
+        ("bufferViews", writer.buffer_views),
+    ];
+    for (name, array) in arrays {
+        if !array.is_empty() {
+            document[name] = json!(array);
+        }
+    }
+    if !writer.buffer.is_empty() {
+        let mut buffer = json!({ "byteLength": writer.buffer.len() });
+        if !glb {
+            let bin_path = path.with_extension("bin");
+            std::fs::write(&bin_path, &writer.buffer)?;
+            buffer["uri"] = json!(bin_path.file_name().unwrap().to_string_lossy());
+        }
+        document["buffers"] = json!([buffer]);
+    }
+    let json = serde_json::to_vec(&document)?;
+
+    if glb {
+        write_glb(path, json, writer.buffer)
+    } else {
+        std::fs::write(path, json)
+    }
+}
+
+// The binary chunk is left out when `bin` is empty
+fn write_glb(path: &Path, mut json: Vec<u8>, mut bin: Vec<u8>) -> io::Result<()> {
+    // Chunks are padded to 4 bytes, JSON with spaces and binary data with zeros
+    json.resize(json.len().next_multiple_of(4), b' ');
+    bin.resize(bin.len().next_multiple_of(4), 0);
+    let bin_chunk_length = if bin.is_empty() { 0 } else { 8 + bin.len() };
+    let total_length = 12 + 8 + json.len() + bin_chunk_length;
+
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(b"glTF")?;
+    out.write_all(&2u32.to_le_bytes())?;
+    out.write_all(&(total_length as u32).to_le_bytes())?;
+    out.write_all(&(json.len() as u32).to_le_bytes())?;
+    out.write_all(b"JSON")?;
+    out.write_all(&json)?;
+    if !bin.is_empty() {
+        out.write_all(&(bin.len() as u32).to_le_bytes())?;
+        out.write_all(b"BIN\0")?;
+        out.write_all(&bin)?;
+    }
+    out.flush()
+}
+
+// The name given in the scene, or one from the index of the glTF node
+fn node_name(node: &SceneNode, index: usize) -> String {
+    if node.name.is_empty() { format!("node_{}", index) } else { node.name.clone() }
+}
+
+#[derive(Default)]
+struct GltfWriter {
+    buffer       : Vec<u8>,
+    buffer_views : Vec<Value>,
+    accessors    : Vec<Value>,
+    meshes       : Vec<Value>,
+    nodes        : Vec<Value>,
+    mesh_index   : HashMap<*const GpuMesh, usize>, // Meshes already written, shared between nodes
+}
+
+impl GltfWriter {
+    fn push_accessor(&mut self, data: &[u8], target: u32, accessor: Value) -> usize {
+        self.buffer.resize(self.buffer.len().next_multiple_of(4), 0);
+        self.buffer_views.push(json!({
+            "buffer": 0,
+            "byteOffset": self.buffer.len(),
+            "byteLength": data.len(),
+            "target": target,
+        }));
+        self.buffer.extend_from_slice(data);
+
+        let mut accessor = accessor;
+        accessor["bufferView"] = json!(self.buffer_views.len() - 1);
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+
+    fn push_floats(&mut self, values: &[f32], components: usize, kind: &str) -> usize {
+        let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let mut accessor = json!({
+            "componentType": FLOAT,
+            "count": values.len() / components,
+            "type": kind,
+        });
+        if kind == "VEC3" {
+            // Required for positions
+            let mut min = [f32::MAX; 3];
+            let mut max = [f32::MIN; 3];
+            for v in values.chunks_exact(3) {
+                for k in 0..3 {
+                    min[k] = min[k].min(v[k]);
+                    max[k] = max[k].max(v[k]);
+                }
+            }
+            accessor["min"] = json!(min);
+            accessor["max"] = json!(max);
+        }
+        self.push_accessor(&bytes, ARRAY_BUFFER, accessor)
+    }
+
+    fn push_mesh(&mut self, mesh: &Mesh) -> usize {
+        let vertex_count = mesh.vertices.len() / 3;
+        let mut attributes = json!({ "POSITION": self.push_floats(&mesh.vertices, 3, "VEC3") });
+        if mesh.normals.len() == vertex_count * 3 {
+            // glTF requires unit normals, so zero ones, as on degenerate faces, point up instead
+            let normals: Vec<f32> = mesh.normals.chunks_exact(3)
+                .flat_map(|n| {
+                    let n = glm::vec3(n[0], n[1], n[2]);
+                    let length = n.norm();
+                    let n = if length > 0.0 { n / length } else { glm::vec3(0.0, 1.0, 0.0) };
+                    [n.x, n.y, n.z]
+                })
+                .collect();
+            attributes["NORMAL"] = json!(self.push_floats(&normals, 3, "VEC3"));
+        }
+        if mesh.colors.len() == vertex_count * 4 {
+            attributes["COLOR_0"] = json!(self.push_floats(&mesh.colors, 4, "VEC4"));
+        }
+        let index_bytes: Vec<u8> = mesh.indices.iter().flat_map(|i| i.to_le_bytes()).collect();
+        let indices = self.push_accessor(&index_bytes, ELEMENT_ARRAY_BUFFER, json!({
+            "componentType": UNSIGNED_INT,
+            "count": mesh.indices.len(),
+            "type": "SCALAR",
+        }));
+        self.meshes.push(json!({
+            "primitives": [{ "attributes": attributes, "indices": indices }],
+        }));
+        self.meshes.len() - 1
+    }
+
+    // Returns the index of the node written for `node`
+    fn push_node_tree(&mut self, node: &SceneNode, meshes: &HashMap<*const GpuMesh, &Mesh>) -> usize {
+        let children: Vec<usize> = node.children.iter()
+            .filter(|c| !c.is_null())
+            .map(|&c| self.push_node_tree(unsafe { &*c }, meshes))
+            .collect();
+
+        let index = self.nodes.len();
+        let mut gltf_node = json!({
+            "name": node_name(node, index),
+            "matrix": node.local_transform().as_slice(), // Column major, like glTF
+        });
+        if !children.is_empty() {
+            gltf_node["children"] = json!(children);
+        }
+        if let Some(mesh) = self.mesh_for(node, meshes) {
+            gltf_node["mesh"] = json!(mesh);
+        }
+        self.nodes.push(gltf_node);
+        index
+    }
+
+    fn push_baked_nodes(
+        &mut self,
+        node: &SceneNode,
+        transformation_so_far: &glm::Mat4,
+        meshes: &HashMap<*const GpuMesh, &Mesh>,
+        roots: &mut Vec<usize>,
+    ) {
+        let current_transform = transformation_so_far * node.local_transform();
+        if let Some(gpu_mesh) = &node.mesh {
+            if let Some(mesh) = meshes.get(&Rc::as_ptr(gpu_mesh)) {
+                let mesh = self.push_mesh(&mesh.transformed(&current_transform));
+                roots.push(self.nodes.len());
+                self.nodes.push(json!({ "name": node_name(node, self.nodes.len()), "mesh": mesh }));
+            }
+        }
+        for &child in &node.children {
+            if !child.is_null() {
+                self.push_baked_nodes(unsafe { &*child }, &current_transform, meshes, roots);
+            }
+        }
+    }
+
+    fn mesh_for(&mut self, node: &SceneNode, meshes: &HashMap<*const GpuMesh, &Mesh>) -> Option<usize> {
+        let key = Rc::as_ptr(node.mesh.as_ref()?);
+        if let Some(&index) = self.mesh_index.get(&key) {
+            return Some(index);
+        }
+        let index = self.push_mesh(meshes.get(&key)?);
+        self.mesh_index.insert(key, index);
+        Some(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless;
+    use crate::vertex_layout::VertexLayout;
+
+    #[test]
+    fn gltf_keeps_node_names_and_writes_unit_normals() {
+        let _context = headless::test_context();
+        let triangle = Mesh {
+            vertices: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+            normals: vec![0.0, 0.0, 0.0, 0.0, 0.0, 2.0, 0.0, 0.0, 1.0],
+            colors: vec![1.0; 12],
+            indices: vec![0, 1, 2],
+            index_count: 3,
+        };
+        let gpu_mesh = Rc::new(unsafe { triangle.upload(&VertexLayout::COMPACT) });
+        let mut root = SceneNode::new();
+        let mut door = SceneNode::from_mesh(gpu_mesh.clone());
+        door.name = "Door".to_string();
+        root.add_child(&door);
+        let meshes = HashMap::from([(Rc::as_ptr(&gpu_mesh), &triangle)]);
+
+        let path = std::env::temp_dir().join(format!("gloom-rs-export-{}.glb", std::process::id()));
+        export_gltf(&root, &meshes, Transforms::Hierarchical, path.to_str().unwrap()).unwrap();
+        let imported = gltf::import(&path);
+        std::fs::remove_file(&path).unwrap();
+        let (document, buffers, _) = imported.unwrap();
+
+        let names: Vec<Option<&str>> = document.nodes().map(|n| n.name()).collect();
+        assert_eq!(names, [Some("Door"), Some("node_1")]);
+        let primitive = document.meshes().next().unwrap().primitives().next().unwrap();
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()].0[..]));
+        for normal in reader.read_normals().unwrap() {
+            assert!((glm::make_vec3(&normal).norm() - 1.0).abs() < 1e-6, "{:?}", normal);
+        }
+    }
+}
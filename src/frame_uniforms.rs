@@ -0,0 +1,108 @@
+// Data shared by every shader for a frame, in a uniform buffer bound at a fixed binding point
+//
+// Shaders get it with `#include "frame.glsl"`. `FrameData` has to match the std140 layout of the
+// `Frame` block declared there, which is why everything is stored as vec4 or padded to one.
+
+use crate::gpu::Buffer;
+use crate::shader::ShaderBuilder;
+
+// Binding point of the Frame block, `layout(binding = 0)` in frame.glsl
+pub const FRAME_BINDING: u32 = 0;
+
+// Size of the light array in frame.glsl, which gets it as a define
+pub const MAX_LIGHTS: usize = 16;
+
+// A builder for shaders including frame.glsl
+pub unsafe fn shader_builder() -> ShaderBuilder {
+    ShaderBuilder::new().define("MAX_LIGHTS", &MAX_LIGHTS.to_string())
+}
+
+// A light in world space, made by light::Light
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct FrameLight {
+    pub position  : [f32; 4], // w is the kind: 0 directional, 1 point, 2 spot
+    pub color     : [f32; 4], // rgb times intensity, w is the range
+    pub direction : [f32; 4], // Normalized, for directional and spot lights
+    pub cone      : [f32; 4], // Cosines of the inner and outer angles of spot lights
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct FrameData {
+    pub view            : [[f32; 4]; 4],
+    pub projection      : [[f32; 4]; 4],
+    pub view_projection : [[f32; 4]; 4],
+    pub light_matrix    : [[f32; 4]; 4], // World space to the clip space of the light casting shadows
+    pub camera_position : [f32; 4], // w unused
+    pub time            : f32,      // Seconds since the start of the program
+    pub light_count     : i32,
+    pub shadow_light    : i32,      // Index of the light casting shadows, -1 for none
+    _padding            : f32,      // Arrays of structs start at a multiple of 16 bytes
+    pub lights          : [FrameLight; MAX_LIGHTS],
+}
+
+impl FrameLight {
+    pub fn is_directional(&self) -> bool {
+        self.position[3] == 0.0
+    }
+}
+
+impl FrameData {
+    // Lights past MAX_LIGHTS are left out
+    pub fn new(view: &glm::Mat4, projection: &glm::Mat4, time: f32, lights: &[FrameLight]) -> Self {
+        let light_count = lights.len().min(MAX_LIGHTS);
+        let mut frame_lights = [FrameLight::default(); MAX_LIGHTS];
+        frame_lights[..light_count].copy_from_slice(&lights[..light_count]);
+
+        let camera_position = glm::inverse(view) * glm::vec4(0.0, 0.0, 0.0, 1.0);
+        FrameData {
+            view: (*view).into(),
+            projection: (*projection).into(),
+            view_projection: (projection * view).into(),
+            light_matrix: glm::Mat4::identity().into(),
+            camera_position: camera_position.into(),
+            time,
+            light_count: light_count as i32,
+            shadow_light: -1,
+            _padding: 0.0,
+            lights: frame_lights,
+        }
+    }
+
+    // Makes light `light` cast shadows, `light_matrix` being its projection from world space
+    pub fn set_shadow(&mut self, light: usize, light_matrix: &glm::Mat4) {
+        self.light_matrix = (*light_matrix).into();
+        self.shadow_light = light as i32;
+    }
+}
+
+pub struct FrameUniforms {
+    buffer: Buffer,
+}
+
+impl FrameUniforms {
+    // Creates the buffer and binds it to FRAME_BINDING, where it stays
+    pub unsafe fn new() -> Self {
+        let buffer = Buffer::new(gl::UNIFORM_BUFFER);
+        buffer.bind();
+        gl::BufferData(
+            gl::UNIFORM_BUFFER,
+            std::mem::size_of::<FrameData>() as isize,
+            std::ptr::null(),
+            gl::DYNAMIC_DRAW,
+        );
+        buffer.bind_base(gl::UNIFORM_BUFFER, FRAME_BINDING);
+        FrameUniforms { buffer }
+    }
+
+    pub unsafe fn update(&self, data: &FrameData) {
+        self.buffer.bind();
+        gl::BufferSubData(
+            gl::UNIFORM_BUFFER,
+            0,
+            std::mem::size_of::<FrameData>() as isize,
+            data as *const FrameData as *const std::ffi::c_void,
+        );
+    }
+}
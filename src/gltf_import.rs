@@ -0,0 +1,327 @@
+// glTF 2.0 (.gltf and .glb) import
+//
+// A Model is the CPU side of a file: its meshes, node hierarchy and animations. Upload the meshes
+// once, then `instantiate` the model as many times as needed, every instance sharing them.
+
+use gltf::animation::util::ReadOutputs;
+use gltf::animation::Interpolation;
+use gltf::mesh::Mode;
+
+use std::rc::Rc;
+
+use crate::gpu::GpuMesh;
+use crate::mesh::Mesh;
+use crate::scene_graph::{Node, SceneNode};
+
+struct NodeDesc {
+    name        : String,
+    translation : glm::Vec3,
+    rotation    : glm::Quat,
+    scale       : glm::Vec3,
+    meshes      : Vec<usize>, // Indices into Model::meshes, one per primitive
+    children    : Vec<usize>,
+}
+
+pub enum Keyframes {
+    Translation(Vec<glm::Vec3>),
+    Rotation(Vec<glm::Quat>),
+    Scale(Vec<glm::Vec3>),
+}
+
+pub struct Channel {
+    pub node          : usize, // Index into Instance::nodes
+    pub times         : Vec<f32>,
+    pub keyframes     : Keyframes, // For cubic splines: in-tangent, value and out-tangent per key
+    pub interpolation : Interpolation,
+}
+
+pub struct Animation {
+    pub name     : String,
+    pub duration : f32,
+    pub channels : Vec<Channel>,
+}
+
+pub struct Model {
+    pub meshes     : Vec<Mesh>,
+    pub animations : Vec<Animation>,
+    nodes          : Vec<NodeDesc>,
+    roots          : Vec<usize>,
+}
+
+pub struct Instance {
+    pub root  : Node,      // Parent of the top level nodes of the scene
+    pub nodes : Vec<Node>, // One per node in the file, in file order
+}
+
+impl Model {
+    pub fn load(path: &str) -> Self {
+        println!("Loading glTF model...");
+        let before = std::time::Instant::now();
+        let (document, buffers, _images) = gltf::import(path)
+            .expect("Failed to load glTF model");
+        let get_buffer = |buffer: gltf::Buffer| Some(&buffers[buffer.index()].0[..]);
+
+        // Every primitive becomes a mesh of its own
+        let mut meshes = vec![];
+        let mut primitive_meshes = vec![];
+        for mesh in document.meshes() {
+            let mut indices = vec![];
+            for primitive in mesh.primitives() {
+                if primitive.mode() != Mode::Triangles {
+                    println!("Skipping primitive of {} drawn as {:?}, only triangles are supported.",
+                        mesh.name().unwrap_or("unnamed mesh"), primitive.mode());
+                    continue;
+                }
+                let reader = primitive.reader(get_buffer);
+                let vertices: Vec<f32> = match reader.read_positions() {
+                    Some(positions) => positions.flatten().collect(),
+                    None => continue,
+                };
+                let num_verts = vertices.len() / 3;
+                let base_color = primitive.material().pbr_metallic_roughness().base_color_factor();
+                let colors = match reader.read_colors(0) {
+                    Some(colors) => colors.into_rgba_f32()
+                        .flat_map(|c| [0, 1, 2, 3].map(|i| c[i] * base_color[i]))
+                        .collect(),
+                    None => base_color.iter().cloned().cycle().take(num_verts * 4).collect(),
+                };
+                let indices_of_primitive: Vec<u32> = match reader.read_indices() {
+                    Some(indices) => indices.into_u32().collect(),
+                    None => (0..num_verts as u32).collect(),
+                };
+                let index_count = indices_of_primitive.len() as i32;
+                let mut m = Mesh {
+                    vertices,
+                    normals: vec![],
+                    colors,
+                    indices: indices_of_primitive,
+                    index_count,
+                };
+                match reader.read_normals() {
+                    Some(normals) => m.normals = normals.flatten().collect(),
+                    None => m.compute_normals(),
+                }
+                indices.push(meshes.len());
+                meshes.push(m);
+            }
+            primitive_meshes.push(indices);
+        }
+
+        let nodes: Vec<NodeDesc> = document.nodes().map(|node| {
+            let (translation, rotation, scale) = node.transform().decomposed();
+            NodeDesc {
+                name: node.name().map_or_else(|| format!("node_{}", node.index()), str::to_owned),
+                translation: glm::make_vec3(&translation),
+                rotation: glm::quat(rotation[0], rotation[1], rotation[2], rotation[3]),
+                scale: glm::make_vec3(&scale),
+                meshes: node.mesh().map_or_else(Vec::new, |m| primitive_meshes[m.index()].clone()),
+                children: node.children().map(|c| c.index()).collect(),
+            }
+        }).collect();
+
+        let roots = document.default_scene()
+            .or_else(|| document.scenes().next())
+            .map_or_else(Vec::new, |scene| scene.nodes().map(|n| n.index()).collect());
+
+        let animations = document.animations().map(|animation| {
+            let channels: Vec<Channel> = animation.channels().filter_map(|channel| {
+                let reader = channel.reader(get_buffer);
+                let times: Vec<f32> = reader.read_inputs()?.collect();
+                if times.is_empty() {
+                    println!("WARNING: Skipping a channel of animation {} without keyframes",
+                        animation.name().unwrap_or("unnamed"));
+                    return None;
+                }
+                let keyframes = match reader.read_outputs()? {
+                    ReadOutputs::Translations(t) => Keyframes::Translation(t.map(|v| glm::make_vec3(&v)).collect()),
+                    ReadOutputs::Rotations(r) => Keyframes::Rotation(r.into_f32()
+                        .map(|q| glm::quat(q[0], q[1], q[2], q[3]))
+                        .collect()),
+                    ReadOutputs::Scales(s) => Keyframes::Scale(s.map(|v| glm::make_vec3(&v)).collect()),
+                    ReadOutputs::MorphTargetWeights(_) => return None, // Not supported
+                };
+                // Sampling indexes the outputs by keyframe, so a mismatch would read past them
+                let outputs = match &keyframes {
+                    Keyframes::Translation(v) | Keyframes::Scale(v) => v.len(),
+                    Keyframes::Rotation(q) => q.len(),
+                };
+                let per_key = match channel.sampler().interpolation() {
+                    Interpolation::CubicSpline => 3,
+                    _ => 1,
+                };
+                if outputs != times.len() * per_key {
+                    println!("WARNING: Skipping a channel of animation {} with {} outputs for {} keyframes",
+                        animation.name().unwrap_or("unnamed"), outputs, times.len());
+                    return None;
+                }
+                Some(Channel {
+                    node: channel.target().node().index(),
+                    times,
+                    keyframes,
+                    interpolation: channel.sampler().interpolation(),
+                })
+            }).collect();
+            let duration = channels.iter()
+                .filter_map(|c| c.times.last().cloned())
+                .fold(0.0, f32::max);
+            Animation {
+                name: animation.name().unwrap_or("unnamed").to_owned(),
+                duration,
+                channels,
+            }
+        }).collect::<Vec<_>>();
+
+        println!("Done in {:.3}ms.", before.elapsed().as_micros() as f32 / 1e3);
+        println!("Loaded {} with {} nodes, {} meshes and {} animations.",
+            path, nodes.len(), meshes.len(), animations.len());
+
+        Model { meshes, animations, nodes, roots }
+    }
+
+    // Builds a scene graph for the model. `gpu_meshes` holds the uploaded version of each mesh.
+    pub fn instantiate(&self, gpu_meshes: &[Rc<GpuMesh>]) -> Instance {
+        assert_eq!(gpu_meshes.len(), self.meshes.len(), "Expected one GPU mesh per mesh");
+        let mut root = SceneNode::new();
+        root.name = "glTF model".to_string();
+        let mut nodes: Vec<Node> = self.nodes.iter().map(|desc| {
+            let mut node = match desc.meshes[..] {
+                [mesh] => SceneNode::from_mesh(gpu_meshes[mesh].clone()),
+                _ => SceneNode::new(),
+            };
+            node.name = desc.name.clone();
+            node.position = desc.translation;
+            node.scale = desc.scale;
+            node.set_rotation_quat(&desc.rotation);
+            node
+        }).collect();
+
+        for (i, desc) in self.nodes.iter().enumerate() {
+            // Nodes with several primitives get a child node per primitive
+            if desc.meshes.len() > 1 {
+                for &mesh in &desc.meshes {
+                    let child = SceneNode::from_mesh(gpu_meshes[mesh].clone());
+                    nodes[i].add_child(&child);
+                }
+            }
+            for &child in &desc.children {
+                let child: *const SceneNode = &**nodes[child];
+                nodes[i].add_child(unsafe { &*child });
+            }
+        }
+        for &r in &self.roots {
+            root.add_child(&nodes[r]);
+        }
+
+        Instance { root, nodes }
+    }
+
+    #[allow(dead_code)]
+    pub fn node_index(&self, name: &str) -> Option<usize> {
+        self.nodes.iter().position(|n| n.name == name)
+    }
+}
+
+impl Animation {
+    // Poses the nodes of an instance as they are `time` seconds into the animation, looping
+    pub fn apply(&self, instance: &mut Instance, time: f32) {
+        let time = if self.duration > 0.0 { time.rem_euclid(self.duration) } else { 0.0 };
+        for channel in &self.channels {
+            let node = &mut instance.nodes[channel.node];
+            match &channel.keyframes {
+                Keyframes::Translation(v) => node.position = channel.sample(v, time, glm::lerp),
+                Keyframes::Scale(v) => node.scale = channel.sample(v, time, glm::lerp),
+                Keyframes::Rotation(v) => {
+                    let q = channel.sample(v, time, glm::quat_slerp);
+                    node.set_rotation_quat(&glm::quat_normalize(&q));
+                }
+            }
+        }
+    }
+}
+
+impl Channel {
+    fn sample<T>(&self, values: &[T], time: f32, lerp: impl Fn(&T, &T, f32) -> T) -> T
+    where
+        T: Copy + std::ops::Add<Output = T> + std::ops::Mul<f32, Output = T>,
+    {
+        let cubic = matches!(self.interpolation, Interpolation::CubicSpline);
+        // Cubic splines store an in-tangent, the value and an out-tangent per key
+        let value = |key: usize| if cubic { values[key * 3 + 1] } else { values[key] };
+
+        // Channels without keyframes, or without an output for each, are left out when loading
+        let last = self.times.len() - 1;
+        let next = self.times.iter().position(|&t| t > time).unwrap_or(last + 1);
+        if next == 0 {
+            return value(0);
+        }
+        if next > last {
+            return value(last);
+        }
+        let prev = next - 1;
+        let dt = self.times[next] - self.times[prev];
+        let t = if dt > 0.0 { (time - self.times[prev]) / dt } else { 0.0 };
+
+        match self.interpolation {
+            Interpolation::Step => value(prev),
+            Interpolation::Linear => lerp(&value(prev), &value(next), t),
+            Interpolation::CubicSpline => {
+                // Hermite spline, the tangents are scaled by the time between the keys
+                let (t2, t3) = (t * t, t * t * t);
+                let out_tangent = values[prev * 3 + 2];
+                let in_tangent = values[next * 3];
+                value(prev) * (2.0 * t3 - 3.0 * t2 + 1.0)
+                    + out_tangent * ((t3 - 2.0 * t2 + t) * dt)
+                    + value(next) * (-2.0 * t3 + 3.0 * t2)
+                    + in_tangent * ((t3 - t2) * dt)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn channels_without_an_output_per_keyframe_are_skipped() {
+        // Two keyframes, then two translations and a single one
+        let floats: Vec<f32> = [0.0, 1.0].iter().cloned().chain((0..15).map(|i| i as f32)).collect();
+        let bytes: Vec<u8> = floats.iter().flat_map(|f| f.to_le_bytes()).collect();
+        let accessor = |offset: usize, count: usize, kind: &str| json!({
+            "bufferView": 0, "byteOffset": offset, "componentType": 5126, "count": count, "type": kind,
+        });
+        let mut times = accessor(0, 2, "SCALAR");
+        times["min"] = json!([0.0]);
+        times["max"] = json!([1.0]);
+        let document = json!({
+            "asset": { "version": "2.0" },
+            "scene": 0,
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [{}],
+            "buffers": [{ "uri": "animation.bin", "byteLength": bytes.len() }],
+            "bufferViews": [{ "buffer": 0, "byteLength": bytes.len() }],
+            "accessors": [times, accessor(8, 2, "VEC3"), accessor(32, 1, "VEC3")],
+            "animations": [{
+                "samplers": [{ "input": 0, "output": 1 }, { "input": 0, "output": 2 }],
+                "channels": [
+                    { "sampler": 0, "target": { "node": 0, "path": "translation" } },
+                    { "sampler": 1, "target": { "node": 0, "path": "translation" } },
+                ],
+            }],
+        });
+
+        let directory = std::env::temp_dir().join(format!("gloom-rs-gltf-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("animation.bin"), &bytes).unwrap();
+        let path = directory.join("animation.gltf");
+        std::fs::write(&path, document.to_string()).unwrap();
+        let model = Model::load(path.to_str().unwrap());
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(model.animations[0].channels.len(), 1);
+        let mut instance = model.instantiate(&[]);
+        model.animations[0].apply(&mut instance, 0.5);
+    }
+}
@@ -0,0 +1,214 @@
+// Golden image tests: fixed scenes rendered offscreen at fixed simulation times, compared with the
+// reference images in tests/golden
+//
+// The models in resources/ are not part of the repository, so the scenes use stand-ins built
+// here: a rolling terrain and helicopters made of boxes with the same parts, pivots and lights.
+//
+// Pictures from different drivers differ slightly, so pixels are compared by perceived color
+// difference and a few of them may differ. On failure the rendered image and a diff image, with
+// differing pixels in red over a faded copy of the reference, are written to target/golden.
+// Run with GOLDEN_UPDATE=1 to write the references instead, after an intended change.
+//
+// The tests need an OpenGL 4.3 context through EGL, which Mesa provides without a GPU or display
+// (llvmpipe). They fail where there is none.
+
+use std::path::PathBuf;
+
+use crate::camera::Camera;
+use crate::headless::{self, OffscreenTarget};
+use crate::mesh::{Helicopter, Mesh};
+use crate::renderer::{self, Renderer};
+use crate::scene::Scene;
+use crate::vertex_layout::VertexLayout;
+
+const WIDTH: i32 = 160;
+const HEIGHT: i32 = 120;
+
+// Largest color difference between two pixels that still counts as the same, from 0 to 1
+const PIXEL_THRESHOLD: f32 = 0.1;
+// Fraction of the pixels allowed to differ
+const MAX_DIFFERING: f32 = 0.005;
+
+// An axis aligned box with a normal per face
+fn cuboid(center: [f32; 3], half_size: [f32; 3], color: [f32; 4]) -> Mesh {
+    let mut mesh = Mesh { vertices: vec![], normals: vec![], colors: vec![], indices: vec![], index_count: 0 };
+    for axis in 0..3 {
+        for &sign in &[-1.0f32, 1.0] {
+            // The two other axes, in an order that makes the face counterclockwise from outside
+            let (u, v) = if sign > 0.0 { ((axis + 1) % 3, (axis + 2) % 3) } else { ((axis + 2) % 3, (axis + 1) % 3) };
+            let first = mesh.vertices.len() as u32 / 3;
+            for &(a, b) in &[(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+                let mut corner = [0.0f32; 3];
+                corner[axis] = sign;
+                corner[u] = a;
+                corner[v] = b;
+                for k in 0..3 {
+                    mesh.vertices.push(center[k] + corner[k] * half_size[k]);
+                }
+                let mut normal = [0.0f32; 3];
+                normal[axis] = sign;
+                mesh.normals.extend_from_slice(&normal);
+                mesh.colors.extend_from_slice(&color);
+            }
+            mesh.indices.extend_from_slice(&[first, first + 1, first + 2, first, first + 2, first + 3]);
+        }
+    }
+    mesh.index_count = mesh.indices.len() as i32;
+    mesh
+}
+
+// Gentle hills on a square of `size` units split into `cells` squares along each side
+fn terrain(size: f32, cells: u32) -> Mesh {
+    let height = |x: f32, z: f32| -12.0 + 3.0 * (x * 0.1).sin() * (z * 0.07).cos();
+    let mut mesh = Mesh { vertices: vec![], normals: vec![], colors: vec![], indices: vec![], index_count: 0 };
+    for j in 0..=cells {
+        for i in 0..=cells {
+            let x = size * (i as f32 / cells as f32 - 0.5);
+            let z = size * (j as f32 / cells as f32 - 0.5);
+            mesh.vertices.extend_from_slice(&[x, height(x, z), z]);
+            mesh.colors.extend_from_slice(&[1.0, 1.0, 1.0, 1.0]);
+        }
+    }
+    for j in 0..cells {
+        for i in 0..cells {
+            let a = j * (cells + 1) + i;
+            let (b, c, d) = (a + 1, a + cells + 1, a + cells + 2);
+            mesh.indices.extend_from_slice(&[a, c, d, a, d, b]);
+        }
+    }
+    mesh.index_count = mesh.indices.len() as i32;
+    mesh.compute_normals();
+    mesh
+}
+
+fn helicopter() -> Helicopter {
+    Helicopter {
+        body:       cuboid([0.0, 1.5, 0.0],   [1.5, 1.5, 4.0],   [0.3, 0.3, 0.3, 1.0]),
+        door:       cuboid([1.55, 1.2, -1.0], [0.05, 0.8, 1.0],  [0.1, 0.1, 0.3, 1.0]),
+        main_rotor: cuboid([0.0, 3.1, 0.0],   [7.0, 0.05, 0.3],  [0.3, 0.1, 0.1, 1.0]),
+        tail_rotor: cuboid([0.35, 2.3, 10.4], [0.05, 1.5, 0.2],  [0.1, 0.3, 0.1, 1.0]),
+    }
+}
+
+// Perceived difference between two colors from 0 to 1, as the distance in YIQ space weighted
+// after how sensitive the eye is to each component (Kotsarenko and Ramos, 2010)
+fn color_difference(a: &image::Rgb<u8>, b: &image::Rgb<u8>) -> f32 {
+    let yiq = |p: &image::Rgb<u8>| {
+        let (r, g, b) = (p[0] as f32, p[1] as f32, p[2] as f32);
+        (
+            0.2989 * r + 0.5866 * g + 0.1145 * b,
+            0.5960 * r - 0.2742 * g - 0.3218 * b,
+            0.2115 * r - 0.5226 * g + 0.3111 * b,
+        )
+    };
+    let (y1, i1, q1) = yiq(a);
+    let (y2, i2, q2) = yiq(b);
+    let (y, i, q) = (y1 - y2, i1 - i2, q1 - q2);
+    // The largest possible value is between black and white
+    ((0.5053 * y * y + 0.299 * i * i + 0.1957 * q * q) / 35215.0).sqrt()
+}
+
+// The number of differing pixels, and an image showing them
+fn compare(expected: &image::RgbImage, actual: &image::RgbImage) -> (usize, image::RgbImage) {
+    let mut differing = 0;
+    let diff = image::RgbImage::from_fn(expected.width(), expected.height(), |x, y| {
+        let (e, a) = (expected.get_pixel(x, y), actual.get_pixel(x, y));
+        if color_difference(e, a) > PIXEL_THRESHOLD {
+            differing += 1;
+            image::Rgb([255, 0, 0])
+        } else {
+            let luma = (e[0] as u32 * 3 + e[1] as u32 * 6 + e[2] as u32) / 10;
+            let faded = (255 - (255 - luma) / 4) as u8;
+            image::Rgb([faded, faded, faded])
+        }
+    });
+    (differing, diff)
+}
+
+// The stand-in scene at `time`, its meshes uploaded in `layout`
+fn render(camera: &Camera, time: f32, layout: &VertexLayout) -> image::RgbImage {
+    let _context = headless::test_context();
+    unsafe {
+        renderer::init_gl();
+        let mut renderer = Renderer::new();
+        let mut scene = Scene::new(terrain(200.0, 40), helicopter(), None, layout);
+        let target = OffscreenTarget::new(WIDTH, HEIGHT);
+        scene.animate(time);
+        renderer.render(&scene, camera, time, target.framebuffer(), WIDTH, HEIGHT);
+        target.read()
+    }
+}
+
+fn reference_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(format!("{}.png", name))
+}
+
+// Checks `actual` against tests/golden/`name`.png, writing what differs as `output` on failure
+fn assert_matches(name: &str, output: &str, actual: &image::RgbImage) {
+    let reference = reference_path(name);
+    let expected = image::open(&reference)
+        .unwrap_or_else(|e| panic!("Failed to read {}, run with GOLDEN_UPDATE=1 to create it: {}", reference.display(), e))
+        .to_rgb8();
+    assert_eq!(expected.dimensions(), actual.dimensions(), "{} has the wrong size", reference.display());
+
+    let (differing, diff) = compare(&expected, actual);
+    let allowed = (MAX_DIFFERING * (WIDTH * HEIGHT) as f32) as usize;
+    if differing > allowed {
+        let directory = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/golden");
+        std::fs::create_dir_all(&directory).unwrap();
+        let actual_path = directory.join(format!("{}.actual.png", output));
+        let diff_path = directory.join(format!("{}.diff.png", output));
+        actual.save(&actual_path).unwrap();
+        diff.save(&diff_path).unwrap();
+        panic!(
+            "{} differs from {} in {} pixels, at most {} may. Wrote {} and {}",
+            output, reference.display(), differing, allowed, actual_path.display(), diff_path.display(),
+        );
+    }
+}
+
+// Renders the stand-in scene at `time` and checks it against tests/golden/`name`.png
+fn check(name: &str, camera: &Camera, time: f32) {
+    let actual = render(camera, time, &VertexLayout::COMPACT);
+    if std::env::var_os("GOLDEN_UPDATE").is_some() {
+        let reference = reference_path(name);
+        std::fs::create_dir_all(reference.parent().unwrap()).unwrap();
+        actual.save(&reference).unwrap();
+        println!("Updated {}", reference.display());
+        return;
+    }
+    assert_matches(name, name, &actual);
+}
+
+#[test]
+fn overview_at_start() {
+    let camera = Camera { translation: glm::vec3(0.0, -30.0, -110.0), rotation: glm::vec2(0.35, 0.0) };
+    check("overview_at_start", &camera, 0.0);
+}
+
+// The beacons blink for the first 0.15s of every second, out of step between the helicopters
+#[test]
+fn overview_with_beacons() {
+    let camera = Camera { translation: glm::vec3(0.0, -30.0, -110.0), rotation: glm::vec2(0.35, 0.0) };
+    check("overview_with_beacons", &camera, 2.05);
+}
+
+// Closer to the ground, where the searchlights land
+#[test]
+fn searchlights_from_above() {
+    let camera = Camera { translation: glm::vec3(0.0, -35.0, -45.0), rotation: glm::vec2(0.75, 0.0) };
+    check("searchlights_from_above", &camera, 4.0);
+}
+
+// Float normals and colors are more precise than the packed ones the references are drawn with,
+// but not so much that it shows
+#[test]
+fn float_vertex_layouts_look_the_same() {
+    if std::env::var_os("GOLDEN_UPDATE").is_some() {
+        return; // The reference is being rewritten
+    }
+    let camera = Camera { translation: glm::vec3(0.0, -35.0, -45.0), rotation: glm::vec2(0.75, 0.0) };
+    for (output, layout) in [("separate", VertexLayout::SEPARATE), ("interleaved", VertexLayout::INTERLEAVED)] {
+        assert_matches("searchlights_from_above", output, &render(&camera, 4.0, &layout));
+    }
+}
@@ -0,0 +1,211 @@
+// Owned OpenGL objects, deleted when dropped
+//
+// The GL context they were created in has to be current on the dropping thread, which holds as
+// long as they stay in the render thread.
+
+use crate::util::{byte_size_of_array, pointer_to_array};
+
+pub struct VertexArray {
+    id: u32,
+}
+
+impl VertexArray {
+    pub unsafe fn new() -> Self {
+        let mut id = 0;
+        gl::GenVertexArrays(1, &mut id);
+        VertexArray { id }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub unsafe fn bind(&self) {
+        gl::BindVertexArray(self.id);
+    }
+}
+
+impl Drop for VertexArray {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteVertexArrays(1, &self.id) }
+    }
+}
+
+pub struct Buffer {
+    id     : u32,
+    target : gl::types::GLenum,
+}
+
+impl Buffer {
+    pub unsafe fn new(target: gl::types::GLenum) -> Self {
+        let mut id = 0;
+        gl::GenBuffers(1, &mut id);
+        Buffer { id, target }
+    }
+
+    // Creates a buffer, binds it and fills it with `data`
+    pub unsafe fn with_data<T>(target: gl::types::GLenum, data: &[T], usage: gl::types::GLenum) -> Self {
+        let buffer = Buffer::new(target);
+        buffer.bind();
+        buffer.set_data(data, usage);
+        buffer
+    }
+
+    #[allow(dead_code)]
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub unsafe fn bind(&self) {
+        gl::BindBuffer(self.target, self.id);
+    }
+
+    // Binds the buffer to an indexed binding point of `target`, e.g. gl::SHADER_STORAGE_BUFFER
+    pub unsafe fn bind_base(&self, target: gl::types::GLenum, index: u32) {
+        gl::BindBufferBase(target, index, self.id);
+    }
+
+    // Replaces the contents, the buffer must be bound
+    pub unsafe fn set_data<T>(&self, data: &[T], usage: gl::types::GLenum) {
+        if data.is_empty() {
+            gl::BufferData(self.target, 0, std::ptr::null(), usage);
+        } else {
+            gl::BufferData(self.target, byte_size_of_array(data), pointer_to_array(data), usage);
+        }
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteBuffers(1, &self.id) }
+    }
+}
+
+pub struct Texture {
+    id     : u32,
+    target : gl::types::GLenum,
+}
+
+impl Texture {
+    pub unsafe fn new(target: gl::types::GLenum) -> Self {
+        let mut id = 0;
+        gl::GenTextures(1, &mut id);
+        Texture { id, target }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    // Binds the texture to texture unit `unit`
+    pub unsafe fn bind(&self, unit: u32) {
+        gl::ActiveTexture(gl::TEXTURE0 + unit);
+        gl::BindTexture(self.target, self.id);
+    }
+}
+
+impl Drop for Texture {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteTextures(1, &self.id) }
+    }
+}
+
+pub struct Framebuffer {
+    id: u32,
+}
+
+impl Framebuffer {
+    pub unsafe fn new() -> Self {
+        let mut id = 0;
+        gl::GenFramebuffers(1, &mut id);
+        Framebuffer { id }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub unsafe fn bind(&self) {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.id);
+    }
+}
+
+impl Drop for Framebuffer {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteFramebuffers(1, &self.id) }
+    }
+}
+
+pub struct Renderbuffer {
+    id: u32,
+}
+
+impl Renderbuffer {
+    // Allocates `width` by `height` pixels of `internal_format`
+    pub unsafe fn new(internal_format: gl::types::GLenum, width: i32, height: i32) -> Self {
+        Renderbuffer::multisampled(0, internal_format, width, height)
+    }
+
+    // Same with `samples` samples per pixel, for multisample anti-aliasing
+    pub unsafe fn multisampled(samples: i32, internal_format: gl::types::GLenum, width: i32, height: i32) -> Self {
+        let mut id = 0;
+        gl::GenRenderbuffers(1, &mut id);
+        gl::BindRenderbuffer(gl::RENDERBUFFER, id);
+        gl::RenderbufferStorageMultisample(gl::RENDERBUFFER, samples, internal_format, width, height);
+        Renderbuffer { id }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+impl Drop for Renderbuffer {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteRenderbuffers(1, &self.id) }
+    }
+}
+
+pub struct Program {
+    id: u32,
+}
+
+impl Program {
+    pub unsafe fn new() -> Self {
+        Program { id: gl::CreateProgram() }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+impl Drop for Program {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteProgram(self.id) }
+    }
+}
+
+// A mesh uploaded to the GPU, ready to be drawn
+pub struct GpuMesh {
+    pub vao         : VertexArray,
+    pub index_count : i32,
+    pub transparent : bool,        // Has vertices with alpha below one, to be blended after the rest
+    _buffers        : Vec<Buffer>, // Kept alive for as long as the VAO refers to them
+}
+
+impl GpuMesh {
+    pub fn new(vao: VertexArray, buffers: Vec<Buffer>, index_count: i32, transparent: bool) -> Self {
+        GpuMesh { vao, index_count, transparent, _buffers: buffers }
+    }
+
+    pub unsafe fn draw(&self) {
+        self.vao.bind();
+        gl::DrawElements(
+            gl::TRIANGLES,
+            self.index_count,
+            gl::UNSIGNED_INT,
+            std::ptr::null(),
+        );
+    }
+}
@@ -0,0 +1,233 @@
+// A small immediate-mode GUI, drawn with the text renderer in a panel along the right edge
+//
+// Widgets are declared anew every frame, each call laying out and drawing the widget and returning
+// whether it was used. Between frames only the open tree nodes, the value being dragged and the
+// scroll position are remembered, keyed by ids the caller keeps unique and stable.
+
+use std::collections::HashSet;
+
+use crate::text::{self, TextRenderer};
+
+const PANEL_WIDTH: f32 = 320.0;
+const PADDING: f32 = 6.0;
+const INDENT: f32 = 12.0;
+// Width of the labels in front of values
+const LABEL_WIDTH: f32 = 110.0;
+// Pixels scrolled per line of the mouse wheel
+const SCROLL_STEP: f32 = 40.0;
+
+const BACKGROUND: [f32; 4] = [0.05, 0.06, 0.09, 0.85];
+const WIDGET: [f32; 4] = [0.16, 0.18, 0.24, 1.0];
+const HOVERED: [f32; 4] = [0.24, 0.28, 0.38, 1.0];
+const ACTIVE: [f32; 4] = [0.33, 0.42, 0.62, 1.0];
+// Values that cannot be changed, and their text
+const LOCKED: [f32; 4] = [0.10, 0.11, 0.14, 1.0];
+const LOCKED_TEXT: [f32; 4] = [0.5, 0.5, 0.5, 1.0];
+
+// The mouse as last seen by the event loop
+#[derive(Clone, Copy, Default)]
+pub struct MouseState {
+    pub position : (f32, f32), // Pixels from the top left corner of the window
+    pub down     : bool,       // Whether the left button is held
+    pub scroll   : f32,        // Lines scrolled since it was last read, positive away from the user
+}
+
+pub struct Gui {
+    pub visible    : bool,
+    mouse          : MouseState,
+    clicked        : bool,            // The left button went down this frame
+    drag           : f32,             // Pixels the mouse moved sideways since the last frame
+    open           : HashSet<String>, // Tree nodes showing their contents
+    active         : Option<String>,  // The value being dragged
+    scroll         : f32,             // Pixels the contents are moved up by
+    content_height : f32,             // Of the last frame, to limit scrolling
+}
+
+// Lays out the widgets of one frame from the top of the panel down
+pub struct Ui<'a> {
+    gui    : &'a mut Gui,
+    text   : &'a mut TextRenderer,
+    left   : f32, // Of the panel
+    bottom : f32, // Of the screen
+    top    : f32, // Where the next widget goes
+    indent : f32,
+}
+
+fn contains(rect: (f32, f32, f32, f32), point: (f32, f32)) -> bool {
+    let (x, y, width, height) = rect;
+    point.0 >= x && point.0 < x + width && point.1 >= y && point.1 < y + height
+}
+
+impl Gui {
+    pub fn new() -> Self {
+        Gui {
+            visible: false,
+            mouse: MouseState::default(),
+            clicked: false,
+            drag: 0.0,
+            open: HashSet::new(),
+            active: None,
+            scroll: 0.0,
+            content_height: 0.0,
+        }
+    }
+
+    // Starts the frame with the panel drawn over a `width` by `height` screen
+    pub fn frame<'a>(&'a mut self, text: &'a mut TextRenderer, mouse: MouseState, width: f32, height: f32) -> Ui<'a> {
+        self.clicked = mouse.down && !self.mouse.down;
+        self.drag = mouse.position.0 - self.mouse.position.0;
+        self.mouse = mouse;
+        if !mouse.down {
+            self.active = None;
+        }
+        let left = width - PANEL_WIDTH;
+        if contains((left, 0.0, PANEL_WIDTH, height), mouse.position) {
+            let max_scroll = (self.content_height - height).max(0.0);
+            self.scroll = (self.scroll - mouse.scroll * SCROLL_STEP).clamp(0.0, max_scroll);
+        }
+        text.rect(left, 0.0, PANEL_WIDTH, height, BACKGROUND);
+        let top = PADDING - self.scroll;
+        Ui { gui: self, text, left, bottom: height, top, indent: 0.0 }
+    }
+}
+
+impl<'a> Ui<'a> {
+    // Takes the next line of the panel, returning its rectangle
+    fn row(&mut self) -> (f32, f32, f32, f32) {
+        let height = self.text.line_height() + 2.0;
+        let x = self.left + PADDING + self.indent;
+        let rect = (x, self.top, self.left + PANEL_WIDTH - PADDING - x, height);
+        self.top += height + 2.0;
+        rect
+    }
+
+    fn visible(&self, rect: (f32, f32, f32, f32)) -> bool {
+        rect.1 + rect.3 > 0.0 && rect.1 < self.bottom
+    }
+
+    fn label_at(&mut self, rect: (f32, f32, f32, f32), text: &str) {
+        self.colored_label_at(rect, text, text::WHITE);
+    }
+
+    fn colored_label_at(&mut self, rect: (f32, f32, f32, f32), text: &str, color: [f32; 4]) {
+        if self.visible(rect) {
+            self.text.text(rect.0 + 2.0, rect.1 + 2.0, text, color);
+        }
+    }
+
+    pub fn label(&mut self, text: &str) {
+        let rect = self.row();
+        self.label_at(rect, text);
+    }
+
+    // A box showing `label`, returning whether it was clicked
+    fn clickable(&mut self, rect: (f32, f32, f32, f32), label: &str) -> bool {
+        let hovered = contains(rect, self.gui.mouse.position) && self.visible(rect);
+        if self.visible(rect) {
+            self.text.rect(rect.0, rect.1, rect.2, rect.3, if hovered { HOVERED } else { WIDGET });
+        }
+        self.label_at(rect, label);
+        hovered && self.gui.clicked
+    }
+
+    pub fn button(&mut self, label: &str) -> bool {
+        let rect = self.row();
+        self.clickable(rect, label)
+    }
+
+    // A line that opens and closes what is below it. Returns whether it is open, in which case the
+    // contents follow indented and must be closed with `tree_pop`.
+    pub fn tree_node(&mut self, id: &str, label: &str) -> bool {
+        let rect = self.row();
+        let open = self.gui.open.contains(id);
+        let marker = if open { "- " } else { "+ " };
+        if self.clickable(rect, &format!("{}{}", marker, label)) {
+            if open {
+                self.gui.open.remove(id);
+            } else {
+                self.gui.open.insert(id.to_string());
+            }
+        }
+        if self.gui.open.contains(id) {
+            self.indent += INDENT;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn tree_pop(&mut self) {
+        self.indent -= INDENT;
+    }
+
+    // A field changing `value` by `speed` per pixel the mouse is dragged sideways
+    fn drag_field(&mut self, rect: (f32, f32, f32, f32), id: String, value: &mut f32, speed: f32) -> bool {
+        let hovered = contains(rect, self.gui.mouse.position) && self.visible(rect);
+        if hovered && self.gui.clicked {
+            self.gui.active = Some(id.clone());
+        }
+        let active = self.gui.active.as_deref() == Some(id.as_str());
+        let changed = active && self.gui.drag != 0.0;
+        if changed {
+            *value += self.gui.drag * speed;
+        }
+        if self.visible(rect) {
+            let color = if active { ACTIVE } else if hovered { HOVERED } else { WIDGET };
+            self.text.rect(rect.0, rect.1, rect.2, rect.3, color);
+        }
+        self.label_at(rect, &format!("{:.2}", value));
+        changed
+    }
+
+    // A field showing `value` greyed out, which does not react to the mouse
+    fn locked_field(&mut self, rect: (f32, f32, f32, f32), value: f32) {
+        if self.visible(rect) {
+            self.text.rect(rect.0, rect.1, rect.2, rect.3, LOCKED);
+        }
+        self.colored_label_at(rect, &format!("{:.2}", value), LOCKED_TEXT);
+    }
+
+    // `label` followed by a field for every value, those marked in `locked` greyed out. Returns
+    // whether any of them changed.
+    fn drag_values(&mut self, id: &str, label: &str, values: &mut [&mut f32], locked: &[bool], speed: f32) -> bool {
+        let (x, y, width, height) = self.row();
+        self.label_at((x, y, LABEL_WIDTH, height), label);
+        let field_width = (width - LABEL_WIDTH) / values.len() as f32;
+        let mut changed = false;
+        for (i, (value, &locked)) in values.iter_mut().zip(locked).enumerate() {
+            let rect = (x + LABEL_WIDTH + i as f32 * field_width, y, field_width - 2.0, height);
+            if locked {
+                self.locked_field(rect, **value);
+            } else {
+                changed |= self.drag_field(rect, format!("{}#{}", id, i), value, speed);
+            }
+        }
+        changed
+    }
+
+    pub fn drag_f32(&mut self, id: &str, label: &str, value: &mut f32, speed: f32) -> bool {
+        self.drag_values(id, label, &mut [value], &[false], speed)
+    }
+
+    pub fn drag_vec3(&mut self, id: &str, label: &str, value: &mut glm::Vec3, speed: f32) -> bool {
+        self.drag_vec3_locked(id, label, value, [false; 3], speed)
+    }
+
+    // Like `drag_vec3`, with the components marked in `locked` shown but not editable
+    pub fn drag_vec3_locked(&mut self,
+                            id: &str,
+                            label: &str,
+                            value: &mut glm::Vec3,
+                            locked: [bool; 3],
+                            speed: f32) -> bool {
+        let [x, y, z] = &mut value.data.0[0];
+        self.drag_values(id, label, &mut [x, y, z], &locked, speed)
+    }
+}
+
+impl<'a> Drop for Ui<'a> {
+    // Remembers how tall the contents were, for scrolling the next frame
+    fn drop(&mut self) {
+        self.gui.content_height = self.top + self.gui.scroll + PADDING;
+    }
+}
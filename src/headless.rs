@@ -0,0 +1,167 @@
+// Rendering without a window, for machines without a display such as CI, and for the tests
+//
+// The OpenGL context comes from EGL, preferably on Mesa's surfaceless platform, which works with
+// the llvmpipe software rasterizer. Drivers without it get a tiny pbuffer surface from the
+// default display instead. Either way the scene is drawn into a framebuffer object of our own and
+// read back into an image.
+
+use khronos_egl as egl;
+
+use crate::camera::Camera;
+use crate::capture::Recording;
+use crate::gpu::{Framebuffer, Renderbuffer};
+use crate::options::{Headless, Options};
+use crate::renderer::{self, Renderer};
+use crate::scene::Scene;
+
+// EGL_PLATFORM_SURFACELESS_MESA from EGL_MESA_platform_surfaceless
+const PLATFORM_SURFACELESS_MESA: egl::Enum = 0x31DD;
+
+type GetPlatformDisplay = extern "system" fn(egl::Enum, *mut std::ffi::c_void, *const egl::Attrib) -> egl::EGLDisplay;
+
+pub struct HeadlessContext {
+    egl     : egl::DynamicInstance<egl::EGL1_4>,
+    display : egl::Display,
+    context : egl::Context,
+    surface : Option<egl::Surface>, // Only for the pbuffer fallback
+}
+
+impl HeadlessContext {
+    // Creates an OpenGL 4.3 core context, makes it current on this thread and loads the function
+    // pointers
+    pub fn new() -> Result<Self, String> {
+        let egl = unsafe { egl::DynamicInstance::<egl::EGL1_4>::load_required() }
+            .map_err(|e| format!("Failed to load libEGL: {}", e))?;
+
+        let surfaceless = egl.get_proc_address("eglGetPlatformDisplayEXT")
+            .map(|f| unsafe { std::mem::transmute::<_, GetPlatformDisplay>(f) })
+            .map(|get_platform_display| {
+                get_platform_display(PLATFORM_SURFACELESS_MESA, std::ptr::null_mut(), std::ptr::null())
+            })
+            .filter(|display| !display.is_null())
+            .map(|display| unsafe { egl::Display::from_ptr(display) })
+            .filter(|&display| egl.initialize(display).is_ok());
+        let (display, surface_type) = match surfaceless {
+            Some(display) => (display, 0),
+            None => {
+                let display = unsafe { egl.get_display(egl::DEFAULT_DISPLAY) }
+                    .ok_or("No EGL display available")?;
+                egl.initialize(display).map_err(|e| format!("Failed to initialize EGL: {}", e))?;
+                (display, egl::PBUFFER_BIT)
+            }
+        };
+
+        egl.bind_api(egl::OPENGL_API).map_err(|e| format!("EGL does not support OpenGL: {}", e))?;
+        let config = egl.choose_first_config(display, &[
+            egl::SURFACE_TYPE, surface_type,
+            egl::RENDERABLE_TYPE, egl::OPENGL_BIT,
+            egl::NONE,
+        ])
+            .map_err(|e| format!("Failed to choose an EGL config: {}", e))?
+            .ok_or("No EGL config supports OpenGL")?;
+        let context = egl.create_context(display, config, None, &[
+            egl::CONTEXT_MAJOR_VERSION, 4,
+            egl::CONTEXT_MINOR_VERSION, 3,
+            egl::CONTEXT_OPENGL_PROFILE_MASK, egl::CONTEXT_OPENGL_CORE_PROFILE_BIT,
+            egl::NONE,
+        ]).map_err(|e| format!("Failed to create an OpenGL 4.3 context: {}", e))?;
+
+        let surface = if surface_type == egl::PBUFFER_BIT {
+            let surface = egl.create_pbuffer_surface(display, config, &[egl::WIDTH, 1, egl::HEIGHT, 1, egl::NONE])
+                .map_err(|e| format!("Failed to create a pbuffer: {}", e))?;
+            Some(surface)
+        } else {
+            None
+        };
+        egl.make_current(display, surface, surface, Some(context))
+            .map_err(|e| format!("Failed to make the context current: {}", e))?;
+
+        gl::load_with(|symbol| egl.get_proc_address(symbol).map_or(std::ptr::null(), |f| f as *const _));
+        Ok(HeadlessContext { egl, display, context, surface })
+    }
+}
+
+// A context for a test, one at a time as the tests run on several threads. Without one the test
+// fails, rather than passing without having checked anything.
+#[cfg(test)]
+pub fn test_context() -> (HeadlessContext, std::sync::MutexGuard<'static, ()>) {
+    static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    let guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let context = HeadlessContext::new()
+        .unwrap_or_else(|e| panic!("Failed to create an offscreen OpenGL context for the test: {}", e));
+    (context, guard)
+}
+
+impl Drop for HeadlessContext {
+    fn drop(&mut self) {
+        let _ = self.egl.make_current(self.display, None, None, None);
+        if let Some(surface) = self.surface {
+            let _ = self.egl.destroy_surface(self.display, surface);
+        }
+        let _ = self.egl.destroy_context(self.display, self.context);
+        let _ = self.egl.terminate(self.display);
+    }
+}
+
+// A framebuffer with color and depth, to render into and read back
+pub struct OffscreenTarget {
+    pub width   : i32,
+    pub height  : i32,
+    framebuffer : Framebuffer,
+    _color      : Renderbuffer,
+    _depth      : Renderbuffer,
+}
+
+impl OffscreenTarget {
+    pub unsafe fn new(width: i32, height: i32) -> Self {
+        let color = Renderbuffer::new(gl::RGBA8, width, height);
+        let depth = Renderbuffer::new(gl::DEPTH_COMPONENT24, width, height);
+        let framebuffer = Framebuffer::new();
+        framebuffer.bind();
+        gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::RENDERBUFFER, color.id());
+        gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::RENDERBUFFER, depth.id());
+        let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+        assert_eq!(status, gl::FRAMEBUFFER_COMPLETE, "Offscreen framebuffer is incomplete");
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+
+        OffscreenTarget { width, height, framebuffer, _color: color, _depth: depth }
+    }
+
+    pub fn framebuffer(&self) -> u32 {
+        self.framebuffer.id()
+    }
+
+    // The rendered picture, top row first
+    #[cfg(test)]
+    pub unsafe fn read(&self) -> image::RgbImage {
+        crate::capture::read_pixels(self.framebuffer.id(), self.width, self.height)
+    }
+}
+
+// Renders the frames asked for in `settings` to PNG files
+pub fn run(options: &Options, settings: &Headless) -> Result<(), String> {
+    let _context = HeadlessContext::new()
+        .map_err(|e| format!("Failed to create an offscreen OpenGL context: {}", e))?;
+    let mut recording = Recording::start(settings.output.clone(), settings.fps)?;
+
+    unsafe {
+        renderer::init_gl();
+        let mut renderer = Renderer::new();
+        let mut scene = Scene::load(&options.resources,
+                                    options.gltf_path.as_deref(),
+                                    options.scene.as_deref(),
+                                    &options.layout);
+        let target = OffscreenTarget::new(settings.width as i32, settings.height as i32);
+        let camera = Camera::default();
+
+        for frame in 0..settings.frames {
+            let time = settings.time + frame as f32 * recording.step;
+            scene.animate(time);
+            renderer.render(&scene, &camera, time, target.framebuffer(), target.width, target.height);
+            recording.capture(target.framebuffer(), target.width, target.height)?;
+            renderer.finish_frame(target.width, target.height);
+        }
+        println!("Wrote {} frames to {}", recording.frames(), recording.directory.display());
+    }
+    Ok(())
+}
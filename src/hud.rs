@@ -0,0 +1,111 @@
+// The heads-up display: frame statistics, the camera and the flight of one helicopter, as text
+// over the picture
+
+use std::collections::VecDeque;
+
+use crate::camera::Camera;
+use crate::profiler::Profiler;
+use crate::renderer::RenderStats;
+use crate::scene::Scene;
+use crate::text::{self, TextRenderer};
+
+// Frames the frame time is averaged over
+const FRAME_WINDOW: usize = 60;
+// Distance from the edges of the screen, in pixels
+const MARGIN: f32 = 8.0;
+
+pub struct Hud {
+    pub visible  : bool,
+    pub selected : usize,          // Helicopter whose flight is shown
+    frame_times  : VecDeque<f32>,  // Seconds, the latest last
+}
+
+impl Hud {
+    pub fn new() -> Self {
+        Hud {
+            visible: true,
+            selected: 0,
+            frame_times: VecDeque::with_capacity(FRAME_WINDOW),
+        }
+    }
+
+    // Records how long the last frame took
+    pub fn frame(&mut self, delta_time: f32) {
+        if self.frame_times.len() == FRAME_WINDOW {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(delta_time);
+    }
+
+    // Moves on to the next helicopter
+    pub fn select_next(&mut self, scene: &Scene) {
+        self.selected = (self.selected + 1) % scene.helicopter_count().max(1);
+    }
+
+    // Queues the lines of the HUD for the next frame
+    pub fn draw(&self,
+                text: &mut TextRenderer,
+                stats: &RenderStats,
+                profiler: &Profiler,
+                camera: &Camera,
+                scene: &Scene,
+                time: f32) {
+        if !self.visible {
+            return;
+        }
+        let frame_time = if self.frame_times.is_empty() {
+            0.0
+        } else {
+            self.frame_times.iter().sum::<f32>() / self.frame_times.len() as f32
+        };
+        let fps = if frame_time > 0.0 { 1.0 / frame_time } else { 0.0 };
+        // Adding zero turns -0.0 into 0.0
+        let position = camera.position().add_scalar(0.0);
+
+        let mut lines = vec![
+            format!("FPS         {:.0}", fps),
+            format!("Frame time  {:.2} ms", frame_time * 1e3),
+            format!("Draw calls  {}", stats.draw_calls),
+            format!("Culled      {}", stats.culled_nodes),
+            format!("Camera      {:.1} {:.1} {:.1}", position.x, position.y, position.z),
+        ];
+
+        if self.selected < scene.helicopter_count() {
+            // Speed from where the helicopter is a moment later
+            let step = 0.05;
+            let heading = scene.helicopter_heading(self.selected, time);
+            let next = scene.helicopter_heading(self.selected, time + step);
+            let speed = glm::distance(&glm::vec2(heading.x, heading.z), &glm::vec2(next.x, next.z)) / step;
+            // The helicopters fly at the height of the terrain origin
+            let altitude = scene.ground_height(heading.x, heading.z)
+                .map_or("-".to_string(), |ground| format!("{:.1}", -ground));
+            // Compass degrees, clockwise from -z as north, +x being east
+            let compass = (-heading.yaw).to_degrees().rem_euclid(360.0);
+
+            lines.push(String::new());
+            lines.push(format!("Helicopter  {}", self.selected + 1));
+            lines.push(format!("Altitude    {}", altitude));
+            lines.push(format!("Speed       {:.1}", speed));
+            lines.push(format!("Heading     {:03.0}", compass));
+        }
+
+        // Averages of the profiled scopes, in milliseconds
+        let averages = profiler.averages();
+        if !averages.is_empty() {
+            let ms = |time: Option<f32>| time.map_or("-".to_string(), |t| format!("{:.2}", t));
+            lines.push(String::new());
+            lines.push(format!("{:<12}{:>7}{:>7}", "Scope", "CPU", "GPU"));
+            for (name, cpu, gpu) in averages {
+                lines.push(format!("{:<12}{:>7}{:>7}", name, ms(cpu), ms(gpu)));
+            }
+            if profiler.dropped > 0 {
+                lines.push(format!("GPU scopes not ready in time: {}", profiler.dropped));
+            }
+        }
+
+        let line_height = text.line_height();
+        for (i, line) in lines.iter().enumerate() {
+            text.text(MARGIN, MARGIN + i as f32 * line_height, line, text::WHITE);
+        }
+    }
+}
@@ -0,0 +1,100 @@
+// Lights attached to scene nodes
+//
+// A light is placed in the space of its node, so it follows the node around: a searchlight
+// attached to a helicopter body turns with it. Every frame the scene graph is walked to gather
+// the lights in world space for the Frame uniform block.
+
+use crate::frame_uniforms::FrameLight;
+use crate::scene_graph::SceneNode;
+
+#[allow(dead_code)]
+#[derive(Clone, Copy)]
+pub enum LightKind {
+    Directional,                      // Sun or moon, only the direction matters
+    Point,                            // Shines equally in every direction
+    Spot { inner: f32, outer: f32 },  // A cone, full strength within `inner` radians of the direction, none past `outer`
+}
+
+#[derive(Clone, Copy)]
+pub struct Light {
+    pub kind      : LightKind,
+    pub position  : glm::Vec3, // In the space of the node
+    pub direction : glm::Vec3, // Where the light points, for directional and spot lights
+    pub color     : glm::Vec3,
+    pub intensity : f32,
+    pub range     : f32,       // Distance at which point and spot lights have faded out
+    pub enabled   : bool,
+}
+
+impl Light {
+    pub fn directional(direction: glm::Vec3, color: glm::Vec3, intensity: f32) -> Light {
+        Light {
+            kind: LightKind::Directional,
+            position: glm::zero(),
+            direction,
+            color,
+            intensity,
+            range: 0.0,
+            enabled: true,
+        }
+    }
+
+    pub fn point(position: glm::Vec3, color: glm::Vec3, intensity: f32, range: f32) -> Light {
+        Light {
+            kind: LightKind::Point,
+            position,
+            direction: glm::zero(),
+            color,
+            intensity,
+            range,
+            enabled: true,
+        }
+    }
+
+    // `inner` and `outer` are angles from the direction, in radians
+    pub fn spot(position: glm::Vec3, direction: glm::Vec3, inner: f32, outer: f32, color: glm::Vec3, intensity: f32, range: f32) -> Light {
+        Light {
+            kind: LightKind::Spot { inner, outer },
+            position,
+            direction,
+            color,
+            intensity,
+            range,
+            enabled: true,
+        }
+    }
+
+    // The light in world space, packed for frame.glsl
+    fn to_frame_light(self, transform: &glm::Mat4) -> FrameLight {
+        let position = transform * self.position.push(1.0);
+        let mut direction = (transform * self.direction.push(0.0)).xyz();
+        if direction != glm::Vec3::zeros() {
+            direction.normalize_mut();
+        }
+        let color = self.color * self.intensity;
+        let (kind, cos_inner, cos_outer) = match self.kind {
+            LightKind::Directional => (0.0, 0.0, 0.0),
+            LightKind::Point => (1.0, 0.0, 0.0),
+            LightKind::Spot { inner, outer } => (2.0, inner.cos(), outer.cos()),
+        };
+        FrameLight {
+            position: [position.x, position.y, position.z, kind],
+            color: [color.x, color.y, color.z, self.range],
+            direction: [direction.x, direction.y, direction.z, 0.0],
+            cone: [cos_inner, cos_outer, 0.0, 0.0],
+        }
+    }
+}
+
+// Appends the enabled lights of the node and its descendants in world space
+pub unsafe fn collect_lights(node: &SceneNode, transformation_so_far: &glm::Mat4, lights: &mut Vec<FrameLight>) {
+    let current_transform = transformation_so_far * node.local_transform();
+    for light in node.lights.iter().filter(|l| l.enabled) {
+        lights.push(light.to_frame_light(&current_transform));
+    }
+    for &child in &node.children {
+        if !child.is_null() {
+            collect_lights(&*child, &current_transform, lights);
+        }
+    }
+}
@@ -10,217 +10,79 @@
 extern crate nalgebra_glm as glm;
 use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
-use std::{mem, os::raw::c_void, ptr};
-use std::ptr::null;
-use glm::vec3;
 
+mod camera;
+mod capture;
+mod debug_draw;
+mod embedded_shaders;
+mod export;
+mod font;
+mod frame_uniforms;
+mod gltf_import;
+#[cfg(test)]
+mod golden_tests;
+mod gpu;
+mod gui;
+mod headless;
+mod hud;
+mod light;
 mod mesh;
+mod options;
+mod postprocess;
+mod preprocess;
+mod profiler;
+mod program_cache;
+mod renderer;
+mod scene;
+mod scene_editor;
+mod scene_file;
 mod shader;
+mod shadow;
+mod simplify;
+mod text;
 mod util;
+mod vertex_layout;
 
 mod scene_graph;
-use scene_graph::SceneNode;
 
 mod toolbox;
 
 use glutin::event::{
     DeviceEvent,
     ElementState::{Pressed, Released},
-    Event, KeyboardInput,
+    Event, KeyboardInput, MouseButton, MouseScrollDelta,
     VirtualKeyCode::{self, *},
     WindowEvent,
 };
 use glutin::event_loop::ControlFlow;
-use crate::mesh::Helicopter;
-use crate::toolbox::simple_heading_animation;
+use crate::camera::Camera;
+use crate::capture::Recording;
+use crate::gui::{Gui, MouseState};
+use crate::hud::Hud;
+use crate::options::Options;
+use crate::renderer::Renderer;
+use crate::scene::Scene;
 
 // initial window size
 const INITIAL_SCREEN_W: u32 = 800;
 const INITIAL_SCREEN_H: u32 = 600;
 
-// == // Helper functions to make interacting with OpenGL a little bit prettier. You *WILL* need these! // == //
+// Frame rate of recorded image sequences, in frames per simulated second
+const RECORDING_FPS: f32 = 30.0;
 
-// Get the size of an arbitrary array of numbers measured in bytes
-// Example usage:  byte_size_of_array(my_array)
-fn byte_size_of_array<T>(val: &[T]) -> isize {
-    std::mem::size_of_val(&val[..]) as isize
-}
-
-// Get the OpenGL-compatible pointer to an arbitrary array of numbers
-// Example usage:  pointer_to_array(my_array)
-fn pointer_to_array<T>(val: &[T]) -> *const c_void {
-    &val[0] as *const T as *const c_void
-}
-
-// Get the size of the given type in bytes
-// Example usage:  size_of::<u64>()
-fn size_of<T>() -> i32 {
-    mem::size_of::<T>() as i32
-}
-
-// Get an offset in bytes for n units of type T, represented as a relative pointer
-// Example usage:  offset::<u64>(4)
-fn offset<T>(n: u32) -> *const c_void {
-    (n * mem::size_of::<T>() as u32) as *const T as *const c_void
-}
-
-unsafe fn draw_scene(node: &scene_graph::SceneNode,
-                     view_projection_matrix: &glm::Mat4,
-                     transformation_so_far: &glm::Mat4) {
-
-    // Compute this node's local transformation
-    let translate_to_ref = glm::translation(&node.reference_point);
-
-    let rotation_x = glm::rotation(node.rotation.x, &glm::vec3(1.0, 0.0, 0.0));
-    let rotation_y = glm::rotation(node.rotation.y, &glm::vec3(0.0, 1.0, 0.0));
-    let rotation_z = glm::rotation(node.rotation.z, &glm::vec3(0.0, 0.0, 1.0));
-    let rotation = rotation_z * rotation_y * rotation_x;
-
-    let translate_from_ref = glm::translation(&-node.reference_point);
-
-    let translate_pos = glm::translation(&node.position);
-
-    let local_transform = translate_pos * translate_to_ref * rotation * translate_from_ref;
-
-    let current_transform = transformation_so_far * local_transform;
-
-
-    // Draw this node if it has geometry
-    if node.index_count > 0 {
-        //Calculates and passes the matrix to shader
-        let mvp = view_projection_matrix * current_transform;
-        gl::UniformMatrix4fv(0, 1, gl::FALSE, mvp.as_ptr());
-
-        gl::UniformMatrix4fv(1, 1, gl::FALSE, current_transform.as_ptr());
-
-        gl::BindVertexArray(node.vao_id);
-        gl::DrawElements(
-            gl::TRIANGLES,
-            node.index_count,
-            gl::UNSIGNED_INT,
-            ptr::null(),
-        );
-    }
-
-    // Recurse to children with the accumulated transformation
-    for &child in &node.children {
-        if !child.is_null() {
-            draw_scene(&*child, view_projection_matrix, &current_transform);
+fn main() {
+    let options = Options::parse(std::env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}\n\n{}", e, options::USAGE);
+        std::process::exit(2);
+    });
+    if let Some(settings) = &options.headless {
+        if let Err(e) = headless::run(&options, settings) {
+            eprintln!("{}", e);
+            std::process::exit(1);
         }
+        return;
     }
-}
-
-// Get a null pointer (equivalent to an offset of 0)
-// ptr::null()
-
-// == // Generate your VAO here
-unsafe fn create_vao(
-    vertices: &Vec<f32>,
-    indices: &Vec<u32>,
-    color: &Vec<f32>,
-    normals: &Vec<f32>,
-) -> u32 {
-    // Implement me!
-
-    // Also, feel free to delete comments :)
-
-    // This should:
-    // * Generate a VAO and bind it
-    // VAO = Vertex Array Object
-    // VAO stores the configuration for how vertex data should be interpreted and rendered.
-    // This code creates a new VAO and makes it the active one.
-    let mut vao = 0;
-    gl::GenVertexArrays(1, &mut vao);
-    gl::BindVertexArray(vao);
-
-    // * Generate a VBO and bind it
-    // VBO = Vertex Buffer Object
-    let mut vbo_positions = 0;
-    gl::GenBuffers(1, &mut vbo_positions);
-    gl::BindBuffer(gl::ARRAY_BUFFER, vbo_positions);
-    // * Fill it with data
-    gl::BufferData(
-        //function to fill the bound buffer with data
-        gl::ARRAY_BUFFER,             //Targets the currently bound VBO
-        byte_size_of_array(vertices), //Allocates size to memory
-        pointer_to_array(vertices),   //pointer to array of vertices
-        gl::STATIC_DRAW,              //tells GL that data wont change often
-    );
-    // * Configure a VAP for the data and enable it
-    gl::VertexAttribPointer(
-        0,
-        3,
-        gl::FLOAT,
-        gl::FALSE,
-        3 * size_of::<f32>(),
-        offset::<f32>(0),
-    );
-    gl::EnableVertexAttribArray(0);
-
-
-    // * Generate a VBO for colors
-    let mut vbo_colors = 0;
-    gl::GenBuffers(1, &mut vbo_colors);
-    gl::BindBuffer(gl::ARRAY_BUFFER, vbo_colors);
-
-    gl::BufferData(
-        gl::ARRAY_BUFFER,
-        byte_size_of_array(color),
-        pointer_to_array(color),
-        gl::STATIC_DRAW,
-    );
-
-    gl::VertexAttribPointer(
-        1,
-        4,
-        gl::FLOAT,
-        gl::FALSE,
-        4 * size_of::<f32>(),
-        offset::<f32>(0),
-    );
-    gl::EnableVertexAttribArray(1);
-    // * Generate a IBO and bind it
-
-    let mut ibo = 0;
-    gl::GenBuffers(1, &mut ibo);
-    gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ibo);
-    // * Fill it with data
-    gl::BufferData(
-        gl::ELEMENT_ARRAY_BUFFER,
-        byte_size_of_array(indices),
-        pointer_to_array(indices),
-        gl::STATIC_DRAW,
-    );
-
-    // * Generate a VBO for normals
-    let mut vbo_normals = 0;
-    gl::GenBuffers(1, &mut vbo_normals);
-    gl::BindBuffer(gl::ARRAY_BUFFER, vbo_normals);
-
-    gl::BufferData(
-        gl::ARRAY_BUFFER,
-        byte_size_of_array(normals),
-        pointer_to_array(normals),
-        gl::STATIC_DRAW,
-    );
-
-    gl::VertexAttribPointer(
-        2,
-        3,
-        gl::FLOAT,
-        gl::FALSE,
-        3*size_of::<f32>(),
-        offset::<f32>(0),
-    );
-    gl::EnableVertexAttribArray(2);
-
-
-    // * Return the ID of the VAO
-
-    vao
-}
 
-fn main() {
     // Set up the necessary objects to deal with windows and event handling
     let el = glutin::event_loop::EventLoop::new();
     let wb = glutin::window::WindowBuilder::new()
@@ -246,6 +108,16 @@ fn main() {
     // Make a reference of this tuple to send to the render thread
     let mouse_delta = Arc::clone(&arc_mouse_delta);
 
+    // Set up shared state of the mouse cursor and buttons, for the GUI
+    let arc_mouse_state = Arc::new(Mutex::new(MouseState::default()));
+    // Make a reference of this state to send to the render thread
+    let mouse_state = Arc::clone(&arc_mouse_state);
+
+    // Set up a shared flag for whether the editor is open, which keeps Q and Escape from quitting
+    let arc_gui_visible = Arc::new(Mutex::new(false));
+    // Make a reference of this flag to send to the render thread
+    let gui_visible = Arc::clone(&arc_gui_visible);
+
     // Set up shared tuple for tracking changes to the window size
     let arc_window_size = Arc::new(Mutex::new((INITIAL_SCREEN_W, INITIAL_SCREEN_H, false)));
     // Make a reference of this tuple to send to the render thread
@@ -262,189 +134,59 @@ fn main() {
             c
         };
 
-        let mut window_aspect_ratio = INITIAL_SCREEN_W as f32 / INITIAL_SCREEN_H as f32;
+        let mut window_width = INITIAL_SCREEN_W as i32;
+        let mut window_height = INITIAL_SCREEN_H as i32;
 
         // Set up openGL
         unsafe {
-            gl::Enable(gl::DEPTH_TEST);
-            gl::DepthFunc(gl::LESS);
-            gl::Disable(gl::CULL_FACE);
-            gl::Disable(gl::MULTISAMPLE);
-            gl::Enable(gl::BLEND);
-            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
-            gl::Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
-            gl::DebugMessageCallback(Some(util::debug_callback), ptr::null());
-
-            // Print some diagnostics
-            println!(
-                "{}: {}",
-                util::get_gl_string(gl::VENDOR),
-                util::get_gl_string(gl::RENDERER)
-            );
-            println!("OpenGL\t: {}", util::get_gl_string(gl::VERSION));
-            println!(
-                "GLSL\t: {}",
-                util::get_gl_string(gl::SHADING_LANGUAGE_VERSION)
-            );
+            renderer::init_gl();
         }
 
-        // == // Set up your VAO around here
-
-        let vertices: Vec<f32> = vec![
-            -0.8, -0.4, -0.7, -0.5, -1.0, -0.7, 0.0, -0.2, -0.7, 0.1, 0.1, 0.1, -0.4, -0.4, 0.1,
-            0.2, -0.6, 0.1, -0.5, 0.4, 0.9, -0.25, -1.0, 0.9, -0.1, 0.5, 0.9,
-        ];
-        let indices: Vec<u32> = vec![6, 7, 8, 3, 4, 5, 0, 1, 2];
-
-        let colors: Vec<f32> = vec![
-            0.1, 1.0, 0.0, 0.3, 0.1, 1.0, 0.0, 0.3, 0.1, 1.0, 0.0, 0.3, 0.1, 0.2, 1.0, 0.3, 0.1,
-            0.2, 1.0, 0.3, 0.1, 0.2, 1.0, 0.3, 1.0, 0.3, 0.2, 0.3, 1.0, 0.3, 0.2, 0.3, 1.0, 0.3,
-            0.2, 0.3,
-        ];
-
-        //let my_vao = unsafe { create_vao(&vertices, &indices, &colors) };
-
-        let terrain_mesh = mesh::Terrain::load(
-            "C:/Users/ranhe/RustroverProjects/gloom-rs/resources/lunarsurface.obj",
-        );
-
-        let helicopter = Helicopter::load("C:/Users/ranhe/RustroverProjects/gloom-rs/resources/helicopter.obj");
-
-        let body = helicopter.body;
-        let door = helicopter.door;
-        let main_rotor = helicopter.main_rotor;
-        let tail_rotor = helicopter.tail_rotor;
-
-        let body_vao = unsafe {
-            create_vao(
-                &body.vertices,
-                &body.indices,
-                &body.colors,
-                &body.normals,
-            )
-        };
-
-        let door_vao = unsafe {
-            create_vao(
-                &door.vertices,
-                &door.indices,
-                &door.colors,
-                &door.normals,
-            )
-        };
-
-        let main_rotor_vao = unsafe {
-            create_vao(
-                &main_rotor.vertices,
-                &main_rotor.indices,
-                &main_rotor.colors,
-                &main_rotor.normals,
-            )
-        };
-
-        let tail_rotor_vao = unsafe {
-            create_vao(
-                &tail_rotor.vertices,
-                &tail_rotor.indices,
-                &tail_rotor.colors,
-                &tail_rotor.normals,
-            )
-        };
-
-        let terrain_vao = unsafe {
-            create_vao(
-                &terrain_mesh.vertices,
-                &terrain_mesh.indices,
-                &terrain_mesh.colors,
-                &terrain_mesh.normals
-            )
+        let mut renderer = unsafe { Renderer::new() };
+        let mut scene = unsafe {
+            Scene::load(&options.resources, options.gltf_path.as_deref(), options.scene.as_deref(), &options.layout)
         };
+        // Where the editor saves the scene
+        let scene_path = options.scene.clone().unwrap_or_else(|| "scene.json".into());
 
-        // == // Set up your shaders here
-
-        // Basic usage of shader helper:
-        // The example code below creates a 'shader' object.
-        // It which contains the field `.program_id` and the method `.activate()`.
-        // The `.` in the path is relative to `Cargo.toml`.
-        // This snippet is not enough to do the exercise, and will need to be modified (outside
-        // of just using the correct path), but it only needs to be called once
-
-        let simple_shader = unsafe {
-            shader::ShaderBuilder::new()
-                .attach_file("C:/Users/ranhe/RustroverProjects/gloom-rs/shaders/simple.frag")
-                .attach_file("C:/Users/ranhe/RustroverProjects/gloom-rs/shaders/simple.vert") //./path/to/simple/shader.file
-                .link()
-        };
-        unsafe {
-            simple_shader.activate();
-        }
-
-
-
-        let mut camera_pos_axis = glm::vec3(0.0f32, 0.0f32, -10.0f32);
-        let mut camera_pos_rotate = glm::vec2(0.0f32, 0.0f32);
+        let mut camera = Camera::default();
+        let mut hud = Hud::new();
+        let mut gui = Gui::new();
 
         // The main rendering loop
-        let first_frame_time = std::time::Instant::now();
-        let mut previous_frame_time = first_frame_time;
-
-
-        let mut helicopters: Vec<scene_graph::Node
-        > = Vec::new();
-        let mut main_rotors: Vec<scene_graph::Node
-        > = Vec::new();
-        let mut tail_rotors: Vec<scene_graph::Node
-        > = Vec::new();
-
-
-        // Creates one terrain node as root node
-        let mut terrain_root_node = SceneNode::new();
-        let mut terrain_node = SceneNode::from_vao(terrain_vao, terrain_mesh.index_count);
-        terrain_root_node.add_child(&terrain_node);
-
-        // Creates 5 helicopters, that all has the same terrain root node
-        for i in 0..5{
-            let mut helicopter_root_node = SceneNode::new();
-
-            let mut body_node = SceneNode::from_vao(body_vao, body.index_count);
-            let mut door_node = SceneNode::from_vao(door_vao, door.index_count);
-            let mut main_rotor_node = SceneNode::from_vao(main_rotor_vao, main_rotor.index_count);
-            let mut tail_rotor_node = SceneNode::from_vao(tail_rotor_vao, tail_rotor.index_count);
-
-            tail_rotor_node.reference_point = vec3(0.35, 2.3, 10.4);
-            main_rotor_node.reference_point = vec3(0.0, 0.0, 0.0);
-
-            terrain_node.add_child(&helicopter_root_node);
-            helicopter_root_node.add_child(&body_node);
-            body_node.add_child(&door_node);
-            body_node.add_child(&main_rotor_node);
-            body_node.add_child(&tail_rotor_node);
-
-            helicopters.push(helicopter_root_node);
-            main_rotors.push(main_rotor_node);
-            tail_rotors.push(tail_rotor_node);
-        }
-
-
-
+        let mut previous_frame_time = std::time::Instant::now();
+        // Seconds simulated since the start, which only follows the clock when not recording
+        let mut elapsed = 0.0f32;
+
+        // Keys held down during the previous frame, to react to a key press only once
+        let mut previous_keys: Vec<VirtualKeyCode> = vec![];
+        // Set when the scene should be exported once it is posed for this frame
+        let mut export_request: Option<export::Transforms> = None;
+        // Set when the frame should be saved once it is drawn
+        let mut screenshot_request = false;
+        let mut recording: Option<Recording> = None;
 
         loop {
-            // Compute time passed since the previous frame and since the start of the program
+            // Compute time passed since the previous frame and since the start of the program.
+            // While recording, every frame moves the same step forward, however long it took.
             let now = std::time::Instant::now();
-            let elapsed = now.duration_since(first_frame_time).as_secs_f32();
-            let delta_time = now.duration_since(previous_frame_time).as_secs_f32();
+            let frame_time = now.duration_since(previous_frame_time).as_secs_f32();
+            let delta_time = match &recording {
+                Some(recording) => recording.step,
+                None => frame_time,
+            };
+            elapsed += delta_time;
             previous_frame_time = now;
+            hud.frame(frame_time);
 
             // Handle resize events
             if let Ok(mut new_size) = window_size.lock() {
                 if new_size.2 {
                     context.resize(glutin::dpi::PhysicalSize::new(new_size.0, new_size.1));
-                    window_aspect_ratio = new_size.0 as f32 / new_size.1 as f32;
-                    (*new_size).2 = false;
+                    window_width = new_size.0 as i32;
+                    window_height = new_size.1 as i32;
+                    new_size.2 = false;
                     println!("Window was resized to {}x{}", new_size.0, new_size.1);
-                    unsafe {
-                        gl::Viewport(0, 0, new_size.0 as i32, new_size.1 as i32);
-                    }
                 }
             }
 
@@ -452,46 +194,139 @@ fn main() {
 
             // Handle keyboard input
             if let Ok(keys) = pressed_keys.lock() {
-                for key in keys.iter() {
+                // The camera stays put while the editor is open
+                for key in keys.iter().filter(|_| !gui.visible) {
                     match key {
                         // The `VirtualKeyCode` enum is defined here:
                         //    https://docs.rs/winit/0.25.0/winit/event/enum.VirtualKeyCode.html
                         VirtualKeyCode::D => {
-                            camera_pos_axis.x += speed*delta_time;
+                            camera.translation.x += speed*delta_time;
                         }
                         VirtualKeyCode::A => {
-                            camera_pos_axis.x -= speed*delta_time;
+                            camera.translation.x -= speed*delta_time;
                         }
                         VirtualKeyCode::W => {
-                            camera_pos_axis.y += speed*delta_time;
+                            camera.translation.y += speed*delta_time;
                         }
                         VirtualKeyCode::S => {
-                            camera_pos_axis.y -= speed*delta_time;
+                            camera.translation.y -= speed*delta_time;
                         }
                         VirtualKeyCode::LShift => {
-                            camera_pos_axis.z += speed*delta_time;
+                            camera.translation.z += speed*delta_time;
                         }
                         VirtualKeyCode::Space => {
-                            camera_pos_axis.z -= speed*delta_time;
+                            camera.translation.z -= speed*delta_time;
                         }
 
                         VirtualKeyCode::Up => {
-                            camera_pos_rotate.x += delta_time;
+                            camera.rotation.x += delta_time;
                         }
                         VirtualKeyCode::Down => {
-                            camera_pos_rotate.x -= delta_time;
+                            camera.rotation.x -= delta_time;
                         }
                         VirtualKeyCode::Left => {
-                            camera_pos_rotate.y += delta_time;
+                            camera.rotation.y += delta_time;
                         }
                         VirtualKeyCode::Right => {
-                            camera_pos_rotate.y -= delta_time;
+                            camera.rotation.y -= delta_time;
                         }
 
                         // default handler:
                         _ => {}
                     }
                 }
+
+                let just_pressed = |key| keys.contains(&key) && !previous_keys.contains(&key);
+                if just_pressed(VirtualKeyCode::F5) {
+                    export_request = Some(export::Transforms::Hierarchical);
+                }
+                if just_pressed(VirtualKeyCode::F6) {
+                    export_request = Some(export::Transforms::Baked);
+                }
+                if just_pressed(VirtualKeyCode::F7) {
+                    let path = "exports/terrain.obj";
+                    match std::fs::create_dir_all("exports").and_then(|_| export::export_obj(&scene.terrain, path)) {
+                        Ok(()) => println!("Exported terrain to {}", path),
+                        Err(e) => println!("Failed to export terrain: {}", e),
+                    }
+                }
+                if just_pressed(VirtualKeyCode::F1) {
+                    hud.visible = !hud.visible;
+                }
+                if just_pressed(VirtualKeyCode::Tab) {
+                    hud.select_next(&scene);
+                }
+                if just_pressed(VirtualKeyCode::F2) {
+                    renderer.debug.show_scene = !renderer.debug.show_scene;
+                }
+                if just_pressed(VirtualKeyCode::F3) {
+                    renderer.debug.xray = !renderer.debug.xray;
+                }
+                if just_pressed(VirtualKeyCode::F4) {
+                    renderer.view_mode = renderer.view_mode.next();
+                    println!("View mode: {}", renderer.view_mode.name());
+                }
+                if just_pressed(VirtualKeyCode::Key6) {
+                    renderer.wireframe = !renderer.wireframe;
+                    println!("Wireframe {}", if renderer.wireframe { "on" } else { "off" });
+                }
+                if just_pressed(VirtualKeyCode::Key7) {
+                    renderer.normal_lines = !renderer.normal_lines;
+                    println!("Normal lines {}", if renderer.normal_lines { "on" } else { "off" });
+                }
+                // Switch post-processing passes on and off
+                let settings = &mut renderer.post.settings;
+                let toggles = [
+                    (VirtualKeyCode::Key1, "MSAA", &mut settings.msaa),
+                    (VirtualKeyCode::Key2, "FXAA", &mut settings.fxaa),
+                    (VirtualKeyCode::Key3, "Bloom", &mut settings.bloom),
+                    (VirtualKeyCode::Key4, "Tonemapping", &mut settings.tonemap),
+                    (VirtualKeyCode::Key5, "Gamma correction", &mut settings.gamma),
+                ];
+                for (key, name, enabled) in toggles {
+                    if just_pressed(key) {
+                        *enabled = !*enabled;
+                        println!("{} {}", name, if *enabled { "on" } else { "off" });
+                    }
+                }
+                if just_pressed(VirtualKeyCode::F8) {
+                    gui.visible = !gui.visible;
+                    if let Ok(mut visible) = gui_visible.lock() {
+                        *visible = gui.visible;
+                    }
+                }
+                if just_pressed(VirtualKeyCode::F10) {
+                    let path = std::path::PathBuf::from(format!("captures/trace_{:.2}s.json", elapsed));
+                    match renderer.profiler.write_trace(&path) {
+                        Ok(()) => println!("Saved trace to {}", path.display()),
+                        Err(e) => println!("Failed to save trace: {}", e),
+                    }
+                }
+                if just_pressed(VirtualKeyCode::F12) {
+                    screenshot_request = true;
+                }
+                if just_pressed(VirtualKeyCode::F9) {
+                    recording = match recording.take() {
+                        Some(finished) => {
+                            println!("Recorded {} frames to {}", finished.frames(), finished.directory.display());
+                            None
+                        }
+                        None => {
+                            let directory = std::path::PathBuf::from(format!("captures/recording_{:.2}s", elapsed));
+                            match Recording::start(directory, RECORDING_FPS) {
+                                Ok(started) => {
+                                    println!("Recording to {}, press F9 again to stop", started.directory.display());
+                                    Some(started)
+                                }
+                                Err(e) => {
+                                    println!("Failed to start recording: {}", e);
+                                    None
+                                }
+                            }
+                        }
+                    };
+                }
+                previous_keys = keys.clone();
             }
             // Handle mouse movement. delta contains the x and y movement of the mouse since last frame in pixels
             if let Ok(mut delta) = mouse_delta.lock() {
@@ -501,96 +336,62 @@ fn main() {
                 *delta = (0.0, 0.0); // reset when done
             }
 
-            // == // Please compute camera transforms here (exercise 2 & 3)
-            unsafe {
-                // Clear the color and depth buffers
-                gl::ClearColor(0.035, 0.046, 0.078, 1.0); // night sky
-                gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
-
-                let oscillation = elapsed.sin();
-                //affine transformations
-                let rotate_x = glm::rotate_x(&glm::Mat4::identity(), camera_pos_rotate.x);
-                let rotate_y = glm::rotate_y(&glm::Mat4::identity(), camera_pos_rotate.y);
-                let rotate = rotate_x * rotate_y;
-                let translate = glm::translation(&camera_pos_axis);
-
-                let projection: glm::Mat4 = glm::perspective(window_aspect_ratio, 0.5, 1.0, 1000.0);
-
-                let final_matrix = projection * rotate * translate;
-
-                //Passing matrix to gpu
-                gl::UniformMatrix4fv(0, 1, gl::FALSE, final_matrix.as_ptr());
-
-                // == // Issue the necessary gl:: commands to draw your scene here
-                /***
-                gl::BindVertexArray(terrain_vao);
-                gl::DrawElements(
-                    gl::TRIANGLES,            // primitive type
-                    terrain_mesh.index_count, // number of indices
-                    gl::UNSIGNED_INT,         // index type
-                    ptr::null(),              // offset
-                );
-                gl::BindVertexArray(body_vao);
-                gl::DrawElements(
-                    gl::TRIANGLES,            // primitive type
-                    body.index_count, // number of indices
-                    gl::UNSIGNED_INT,         // index type
-                    ptr::null(),              // offset
-                );
-                gl::BindVertexArray(door_vao);
-                gl::DrawElements(
-                    gl::TRIANGLES,            // primitive type
-                    door.index_count, // number of indices
-                    gl::UNSIGNED_INT,         // index type
-                    ptr::null(),              // offset
-                );
-
-                gl::BindVertexArray(main_rotor_vao);
-                gl::DrawElements(
-                    gl::TRIANGLES,            // primitive type
-                    main_rotor.index_count, // number of indices
-                    gl::UNSIGNED_INT,         // index type
-                    ptr::null(),              // offset
-                );
-
-                gl::BindVertexArray(tail_rotor_vao);
-                gl::DrawElements(
-                    gl::TRIANGLES,            // primitive type
-                    tail_rotor.index_count, // number of indices
-                    gl::UNSIGNED_INT,         // index type
-                    ptr::null(),              // offset
-                );
-
-
-**/
-                for (i, heli) in helicopters.iter_mut().enumerate() {
-                    let offsets = 1.2 * i as f32 ;
-                    let heading = simple_heading_animation(elapsed+offsets);
-
-                    heli.position.x = heading.x;
-                    heli.position.z = heading.z;
-                    heli.rotation.x = heading.roll;
-                    heli.rotation.y = heading.yaw;
-                    heli.rotation.z = heading.pitch;
-
-                    let main_rotor_node = &mut *main_rotors[i];
-                    main_rotor_node.rotation.y = elapsed * 10.0;
-
-                    let tail_rotor_node = &mut *tail_rotors[i];
-                    tail_rotor_node.rotation.x = elapsed * 20.0;
-
-
+            renderer.profiler.begin_cpu("update");
+            scene.animate(elapsed);
+            renderer.profiler.end_cpu();
+
+            if let Some(transforms) = export_request.take() {
+                let path = format!("exports/scene_{:.2}s.glb", elapsed);
+                let result = std::fs::create_dir_all("exports").and_then(|_| {
+                    export::export_gltf(&scene.root, &scene.mesh_library(), transforms, &path)
+                });
+                match result {
+                    Ok(()) => println!("Exported scene to {}", path),
+                    Err(e) => println!("Failed to export scene: {}", e),
                 }
-                draw_scene(&terrain_root_node,&final_matrix, &glm::Mat4::identity());
-
-
-
-
-
-
-
             }
 
+            unsafe {
+                renderer.reload_shaders();
+                hud.draw(&mut renderer.text, &renderer.stats, &renderer.profiler, &camera, &scene, elapsed);
+                if gui.visible {
+                    // Scrolling is counted from one frame to the next
+                    let mouse = match mouse_state.lock() {
+                        Ok(mut state) => {
+                            let mouse = *state;
+                            state.scroll = 0.0;
+                            mouse
+                        }
+                        Err(_) => MouseState::default(),
+                    };
+                    let mut ui = gui.frame(&mut renderer.text, mouse, window_width as f32, window_height as f32);
+                    scene_editor::panel(&mut ui, &mut scene, &scene_path);
+                }
+                renderer.render(&scene, &camera, elapsed, 0, window_width, window_height);
+
+                // Read back the scene before the HUD and GUI text go over it. A minimized
+                // window has nothing to read.
+                let visible = window_width > 0 && window_height > 0;
+                if screenshot_request && visible {
+                    screenshot_request = false;
+                    let path = capture::screenshot_path(elapsed);
+                    let image = capture::read_pixels(0, window_width, window_height);
+                    let result = std::fs::create_dir_all("captures")
+                        .map_err(|e| format!("Failed to create captures: {}", e))
+                        .and_then(|_| capture::save_png(&image, &path));
+                    match result {
+                        Ok(()) => println!("Saved screenshot to {}", path.display()),
+                        Err(e) => println!("Failed to save screenshot: {}", e),
+                    }
+                }
+                if let Some(active) = recording.as_mut().filter(|_| visible) {
+                    if let Err(e) = active.capture(0, window_width, window_height) {
+                        println!("Stopped recording: {}", e);
+                        recording = None;
+                    }
+                }
+                renderer.finish_frame(window_width, window_height);
+            }
 
             // Display the new color buffer on the display
             context.swap_buffers().unwrap(); // we use "double buffering" to avoid artifacts
@@ -605,7 +406,7 @@ fn main() {
     let render_thread_healthy = Arc::new(RwLock::new(true));
     let render_thread_watchdog = Arc::clone(&render_thread_healthy);
     thread::spawn(move || {
-        if !render_thread.join().is_ok() {
+        if render_thread.join().is_err() {
             if let Ok(mut health) = render_thread_watchdog.write() {
                 println!("Render thread panicked!");
                 *health = false;
@@ -619,7 +420,7 @@ fn main() {
 
         // Terminate program if render thread panics
         if let Ok(health) = render_thread_healthy.read() {
-            if *health == false {
+            if !*health {
                 *control_flow = ControlFlow::Exit;
             }
         }
@@ -673,8 +474,10 @@ fn main() {
                     }
                 }
 
-                // Handle Escape and Q keys separately
+                // Handle Escape and Q keys separately, unless the editor is open
+                let editing = arc_gui_visible.lock().map(|visible| *visible).unwrap_or(false);
                 match keycode {
+                    _ if editing => {}
                     Escape => {
                         *control_flow = ControlFlow::Exit;
                     }
@@ -684,6 +487,34 @@ fn main() {
                     _ => {}
                 }
             }
+            // Keep track of the cursor and the left button for the GUI
+            Event::WindowEvent {
+                event: WindowEvent::CursorMoved { position, .. },
+                ..
+            } => {
+                if let Ok(mut mouse) = arc_mouse_state.lock() {
+                    mouse.position = (position.x as f32, position.y as f32);
+                }
+            }
+            Event::WindowEvent {
+                event: WindowEvent::MouseInput { state, button: MouseButton::Left, .. },
+                ..
+            } => {
+                if let Ok(mut mouse) = arc_mouse_state.lock() {
+                    mouse.down = state == Pressed;
+                }
+            }
+            Event::WindowEvent {
+                event: WindowEvent::MouseWheel { delta, .. },
+                ..
+            } => {
+                if let Ok(mut mouse) = arc_mouse_state.lock() {
+                    mouse.scroll += match delta {
+                        MouseScrollDelta::LineDelta(_, y) => y,
+                        MouseScrollDelta::PixelDelta(position) => position.y as f32 / 20.0,
+                    };
+                }
+            }
             Event::DeviceEvent {
                 event: DeviceEvent::MouseMotion { delta },
                 ..
@@ -1,4 +1,6 @@
-use tobj;
+use crate::gpu::GpuMesh;
+use crate::simplify;
+use crate::vertex_layout::VertexLayout;
 
 // internal helper
 fn generate_color_vec(color: [f32; 4], num: usize) -> Vec<f32> {
@@ -27,10 +29,201 @@ impl Mesh {
             index_count,
         }
     }
+
+    // Creates a VAO holding the mesh, stored according to `layout`
+    pub unsafe fn upload(&self, layout: &VertexLayout) -> GpuMesh {
+        layout.upload(self)
+    }
+
+    // Replaces the normals with the area-weighted average of the faces around each vertex
+    pub fn compute_normals(&mut self) {
+        let mut normals = vec![0.0f32; self.vertices.len()];
+        let p = |i: u32| {
+            let i = i as usize * 3;
+            glm::vec3(self.vertices[i], self.vertices[i + 1], self.vertices[i + 2])
+        };
+        for tri in self.indices.chunks_exact(3) {
+            let n = glm::cross(&(p(tri[1]) - p(tri[0])), &(p(tri[2]) - p(tri[0])));
+            for &v in tri {
+                for k in 0..3 {
+                    normals[v as usize * 3 + k] += n[k];
+                }
+            }
+        }
+        for n in normals.chunks_exact_mut(3) {
+            let len = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+            if len > 0.0 {
+                n.iter_mut().for_each(|x| *x /= len);
+            }
+        }
+        self.normals = normals;
+    }
+
+    // A copy with the positions and normals moved by `transform`
+    pub fn transformed(&self, transform: &glm::Mat4) -> Mesh {
+        let normal_matrix = glm::mat4_to_mat3(&glm::transpose(&glm::inverse(transform)));
+        Mesh {
+            vertices: self.vertices.chunks_exact(3)
+                .flat_map(|p| {
+                    let p = transform * glm::vec4(p[0], p[1], p[2], 1.0);
+                    [p.x, p.y, p.z]
+                })
+                .collect(),
+            normals: self.normals.chunks_exact(3)
+                .flat_map(|n| {
+                    let n = normal_matrix * glm::vec3(n[0], n[1], n[2]);
+                    // Zero normals stay zero instead of becoming NaN
+                    let length = n.norm();
+                    let n = if length > 0.0 { n / length } else { n };
+                    [n.x, n.y, n.z]
+                })
+                .collect(),
+            colors: self.colors.clone(),
+            indices: self.indices.clone(),
+            index_count: self.index_count,
+        }
+    }
+
+    // Whether any vertex is see-through
+    pub fn is_transparent(&self) -> bool {
+        self.colors.chunks_exact(4).any(|c| c[3] < 1.0)
+    }
+
+    // Center and radius of a sphere enclosing all vertices, used to estimate the size on screen
+    pub fn bounding_sphere(&self) -> (glm::Vec3, f32) {
+        let mut min = glm::vec3(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = glm::vec3(f32::MIN, f32::MIN, f32::MIN);
+        for p in self.vertices.chunks_exact(3) {
+            let p = glm::vec3(p[0], p[1], p[2]);
+            min = glm::min2(&min, &p);
+            max = glm::max2(&max, &p);
+        }
+        if self.vertices.is_empty() {
+            return (glm::zero(), 0.0);
+        }
+        let center = (min + max) * 0.5;
+        let radius = self.vertices.chunks_exact(3)
+            .map(|p| glm::distance(&center, &glm::vec3(p[0], p[1], p[2])))
+            .fold(0.0, f32::max);
+        (center, radius)
+    }
+
+    // A copy reduced to roughly `ratio` of the triangles by collapsing the least noticeable edges
+    pub fn simplified(&self, ratio: f32) -> Mesh {
+        let target = (self.indices.len() / 3) as f32 * ratio.clamp(0.0, 1.0);
+        simplify::simplify(self, target as usize)
+    }
+
+    // Successively coarser levels of detail, each with `ratio` of the triangles of the one before.
+    // The full resolution mesh itself is not included.
+    pub fn lod_chain(&self, levels: usize, ratio: f32) -> Vec<Mesh> {
+        let mut chain: Vec<Mesh> = Vec::with_capacity(levels);
+        for _ in 0..levels {
+            let previous = chain.last().unwrap_or(self);
+            let next = previous.simplified(ratio);
+            if next.index_count == 0 || next.index_count >= previous.index_count {
+                break; // Nothing left to remove
+            }
+            chain.push(next);
+        }
+        chain
+    }
 }
 
 // Lunar terrain
 
+// Height of triangle `a`, `b`, `c` straight above or below `x`, `z`, if it covers that point
+fn triangle_height(a: &glm::Vec3, b: &glm::Vec3, c: &glm::Vec3, x: f32, z: f32) -> Option<f32> {
+    // Barycentric coordinates of the point in the triangle as seen from above
+    let area = (b.x - a.x) * (c.z - a.z) - (c.x - a.x) * (b.z - a.z);
+    if area == 0.0 {
+        return None;
+    }
+    let u = ((b.x - x) * (c.z - z) - (c.x - x) * (b.z - z)) / area;
+    let v = ((c.x - x) * (a.z - z) - (a.x - x) * (c.z - z)) / area;
+    let w = 1.0 - u - v;
+    if u >= 0.0 && v >= 0.0 && w >= 0.0 {
+        Some(u * a.y + v * b.y + w * c.y)
+    } else {
+        None
+    }
+}
+
+// The triangles of a mesh sorted into a grid of cells as seen from above, so that finding the
+// surface under a point only tests the few triangles of its cell
+pub struct HeightGrid {
+    min       : glm::Vec2,          // Corner of the first cell, in x and z
+    cell_size : glm::Vec2,
+    columns   : usize,
+    rows      : usize,
+    triangles : Vec<[glm::Vec3; 3]>,
+    cells     : Vec<Vec<u32>>,     // Triangles overlapping each cell, row by row
+}
+
+impl HeightGrid {
+    pub fn new(mesh: &Mesh) -> Self {
+        let vertex = |i: u32| {
+            let i = i as usize * 3;
+            glm::vec3(mesh.vertices[i], mesh.vertices[i + 1], mesh.vertices[i + 2])
+        };
+        let triangles: Vec<[glm::Vec3; 3]> = mesh.indices.chunks_exact(3)
+            .map(|t| [vertex(t[0]), vertex(t[1]), vertex(t[2])])
+            .collect();
+
+        let mut min = glm::vec2(f32::MAX, f32::MAX);
+        let mut max = glm::vec2(f32::MIN, f32::MIN);
+        for p in triangles.iter().flatten() {
+            min = glm::min2(&min, &p.xz());
+            max = glm::max2(&max, &p.xz());
+        }
+        if triangles.is_empty() {
+            min = glm::zero();
+            max = glm::zero();
+        }
+        // About one triangle per cell
+        let side = (triangles.len() as f32).sqrt().ceil().max(1.0) as usize;
+        let cell_size = ((max - min) / side as f32).map(|size| size.max(f32::EPSILON));
+        let mut grid = HeightGrid {
+            min,
+            cell_size,
+            columns: side,
+            rows: side,
+            triangles,
+            cells: vec![vec![]; side * side],
+        };
+
+        for (i, triangle) in grid.triangles.iter().enumerate() {
+            let low = triangle.iter().fold(glm::vec2(f32::MAX, f32::MAX), |m, p| glm::min2(&m, &p.xz()));
+            let high = triangle.iter().fold(glm::vec2(f32::MIN, f32::MIN), |m, p| glm::max2(&m, &p.xz()));
+            let (first, last) = (grid.cell(low.x, low.y), grid.cell(high.x, high.y));
+            for row in first.1..=last.1 {
+                for column in first.0..=last.0 {
+                    grid.cells[row * grid.columns + column].push(i as u32);
+                }
+            }
+        }
+        grid
+    }
+
+    // Column and row of the cell holding `x`, `z`, the points outside going to the nearest cell
+    fn cell(&self, x: f32, z: f32) -> (usize, usize) {
+        let column = ((x - self.min.x) / self.cell_size.x).max(0.0) as usize;
+        let row = ((z - self.min.y) / self.cell_size.y).max(0.0) as usize;
+        (column.min(self.columns - 1), row.min(self.rows - 1))
+    }
+
+    // Height of the highest surface straight above or below `x`, `z`, if any triangle covers it
+    pub fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        let (column, row) = self.cell(x, z);
+        self.cells[row * self.columns + column].iter()
+            .filter_map(|&i| {
+                let [a, b, c] = &self.triangles[i as usize];
+                triangle_height(a, b, c, x, z)
+            })
+            .reduce(f32::max)
+    }
+}
+
 pub struct Terrain;
 impl Terrain {
     pub fn load(path: &str) -> Mesh {
@@ -47,7 +240,7 @@ impl Terrain {
         let after = std::time::Instant::now();
         println!("Done in {:.3}ms.", after.duration_since(before).as_micros() as f32 / 1e3);
 
-        if models.len() > 1 || models.len() == 0 {
+        if models.len() > 1 || models.is_empty() {
             panic!("Please use a model with a single mesh!")
             // You could try merging the vertices and indices
             // of the separate meshes into a single mesh.
@@ -79,7 +272,7 @@ pub struct Helicopter {
 use std::ops::Index;
 impl Index<usize> for Helicopter {
     type Output = Mesh;
-    fn index<'a>(&'a self, i: usize) -> &'a Mesh {
+    fn index(&self, i: usize) -> &Mesh {
         match i {
             0 => &self.body,
             1 => &self.main_rotor,
@@ -122,3 +315,50 @@ impl Helicopter {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A `n` by `n` grid of quads over x and z in [0, n], raised to `height`
+    fn heightfield(n: u32, height: impl Fn(f32, f32) -> f32) -> Mesh {
+        let mut mesh = Mesh { vertices: vec![], normals: vec![], colors: vec![], indices: vec![], index_count: 0 };
+        for j in 0..=n {
+            for i in 0..=n {
+                let (x, z) = (i as f32, j as f32);
+                mesh.vertices.extend_from_slice(&[x, height(x, z), z]);
+            }
+        }
+        for j in 0..n {
+            for i in 0..n {
+                let a = j * (n + 1) + i;
+                let (b, c, d) = (a + 1, a + n + 1, a + n + 2);
+                mesh.indices.extend_from_slice(&[a, d, b, a, c, d]);
+            }
+        }
+        mesh.index_count = mesh.indices.len() as i32;
+        mesh
+    }
+
+    #[test]
+    fn height_grid_follows_the_surface() {
+        let slope = |x: f32, z: f32| 0.5 * x - 0.25 * z + 2.0;
+        let grid = HeightGrid::new(&heightfield(16, slope));
+        for &(x, z) in &[(0.0, 0.0), (3.3, 7.9), (8.0, 8.0), (15.99, 0.01), (16.0, 16.0)] {
+            let height = grid.height_at(x, z).unwrap();
+            assert!((height - slope(x, z)).abs() < 1e-4, "{} at {}, {}", height, x, z);
+        }
+        assert_eq!(grid.height_at(-1.0, 4.0), None);
+        assert_eq!(grid.height_at(4.0, 17.0), None);
+    }
+
+    #[test]
+    fn height_grid_takes_the_highest_surface() {
+        let mut mesh = heightfield(4, |_, _| 0.0);
+        let upper = heightfield(4, |_, _| 3.0);
+        let offset = mesh.vertices.len() as u32 / 3;
+        mesh.vertices.extend_from_slice(&upper.vertices);
+        mesh.indices.extend(upper.indices.iter().map(|i| i + offset));
+        assert_eq!(HeightGrid::new(&mesh).height_at(1.5, 2.5), Some(3.0));
+    }
+}
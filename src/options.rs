@@ -0,0 +1,150 @@
+// Command line options
+
+use std::path::PathBuf;
+
+use crate::vertex_layout::VertexLayout;
+
+pub const USAGE: &str = "\
+Usage: gloom-rs [options] [model.gltf|model.glb]
+
+Options:
+    --resources DIR   Directory holding lunarsurface.obj and helicopter.obj (default: resources)
+    --scene FILE      Apply the node and animation tweaks in FILE, where the editor saves them
+                      (default: none, the editor saves to scene.json)
+    --vertex-layout L How vertices are stored on the GPU: separate, interleaved or compact
+                      (default: compact)
+    --headless        Render offscreen to PNG files instead of opening a window
+    --size WxH        Resolution of the headless frames (default: 800x600)
+    --time SECONDS    Simulation time of the first headless frame (default: 0)
+    --frames N        Number of headless frames to render (default: 1)
+    --fps FPS         Frames per simulated second between headless frames (default: 30)
+    --output DIR      Where headless frames are written as frame_NNNN.png (default: frames)";
+
+pub struct Options {
+    pub gltf_path : Option<String>,  // A glTF model to show in addition to the helicopters
+    pub resources : PathBuf,
+    pub scene     : Option<PathBuf>, // Tweaks to the scene saved from the editor
+    pub layout    : VertexLayout,
+    pub headless  : Option<Headless>,
+}
+
+pub struct Headless {
+    pub width  : u32,
+    pub height : u32,
+    pub time   : f32,
+    pub frames : u32,
+    pub fps    : f32,
+    pub output : PathBuf,
+}
+
+impl Default for Headless {
+    fn default() -> Self {
+        Headless {
+            width: 800,
+            height: 600,
+            time: 0.0,
+            frames: 1,
+            fps: 30.0,
+            output: PathBuf::from("frames"),
+        }
+    }
+}
+
+// Options that only apply to headless rendering
+const HEADLESS_OPTIONS: [&str; 5] = ["--size", "--time", "--frames", "--fps", "--output"];
+
+fn parse_size(value: &str) -> Option<(u32, u32)> {
+    let (w, h) = value.split_once('x')?;
+    match (w.parse(), h.parse()) {
+        (Ok(w), Ok(h)) if w > 0 && h > 0 => Some((w, h)),
+        _ => None,
+    }
+}
+
+impl Options {
+    // `args` without the program name
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+        let mut gltf_path = None;
+        let mut resources = PathBuf::from("resources");
+        let mut scene = None;
+        let mut layout = VertexLayout::COMPACT;
+        let mut headless = false;
+        let mut settings = Headless::default();
+        let mut headless_option = None; // The first option only meaningful with --headless
+
+        while let Some(arg) = args.next() {
+            if arg == "--headless" {
+                headless = true;
+                continue;
+            }
+            if !arg.starts_with("--") {
+                if gltf_path.replace(arg).is_some() {
+                    return Err("Only one glTF model can be given".to_string());
+                }
+                continue;
+            }
+            let value = args.next().ok_or_else(|| format!("Missing value for {}", arg))?;
+            let invalid = || format!("Invalid value for {}: {}", arg, value);
+            if HEADLESS_OPTIONS.contains(&arg.as_str()) && headless_option.is_none() {
+                headless_option = Some(arg.clone());
+            }
+            match arg.as_str() {
+                "--resources" => resources = PathBuf::from(&value),
+                "--scene" => scene = Some(PathBuf::from(&value)),
+                "--vertex-layout" => layout = VertexLayout::from_name(&value).ok_or_else(invalid)?,
+                "--size" => {
+                    let (width, height) = parse_size(&value).ok_or_else(invalid)?;
+                    settings.width = width;
+                    settings.height = height;
+                }
+                "--time" => settings.time = value.parse().map_err(|_| invalid())?,
+                "--frames" => settings.frames = value.parse().map_err(|_| invalid())?,
+                "--fps" => {
+                    settings.fps = value.parse().map_err(|_| invalid())?;
+                    if settings.fps <= 0.0 {
+                        return Err(invalid());
+                    }
+                }
+                "--output" => settings.output = PathBuf::from(&value),
+                _ => return Err(format!("Unknown option {}", arg)),
+            }
+        }
+
+        if let (false, Some(option)) = (headless, headless_option) {
+            return Err(format!("{} can only be used with --headless", option));
+        }
+
+        Ok(Options {
+            gltf_path,
+            resources,
+            scene,
+            layout,
+            headless: if headless { Some(settings) } else { None },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn headless_options_need_headless() {
+        for option in HEADLESS_OPTIONS {
+            let value = if option == "--size" { "64x48" } else { "1" };
+            assert!(parse(&[option, value]).is_err(), "{} accepted without --headless", option);
+            assert!(parse(&[option, value, "--headless"]).is_ok());
+        }
+    }
+
+    #[test]
+    fn headless_settings_are_parsed() {
+        let options = parse(&["--headless", "--size", "64x48", "--frames", "3"]).unwrap();
+        let headless = options.headless.unwrap();
+        assert_eq!((headless.width, headless.height, headless.frames), (64, 48, 3));
+    }
+}
@@ -0,0 +1,278 @@
+// Post-processing: the scene is drawn in HDR into framebuffers of our own, then brought to the
+// screen by a chain of full-screen passes
+//
+//   scene, 16-bit float color, with 4x MSAA resolved by a blit
+//     -> bloom: the parts brighter than white at half resolution, blurred
+//     -> composite: scene plus bloom, exposure, ACES tonemapping and gamma correction
+//     -> FXAA
+//
+// Every pass can be switched on and off in PostSettings while running.
+
+use crate::gpu::{Framebuffer, Renderbuffer, Texture, VertexArray};
+use crate::shader::{Shader, ShaderBuilder};
+
+// Texture units the passes read from, clear of the shadow map on unit 1
+const SCENE_UNIT: u32 = 2;
+const BLOOM_UNIT: u32 = 3;
+
+const MSAA_SAMPLES: i32 = 4;
+// Blur passes, alternating between horizontal and vertical. Even, so that the result ends up
+// back in the first bloom target.
+const BLOOM_BLUR_PASSES: usize = 6;
+
+#[derive(Clone, Copy)]
+pub struct PostSettings {
+    pub msaa            : bool,
+    pub fxaa            : bool,
+    pub bloom           : bool,
+    pub tonemap         : bool, // ACES, or clamping when off
+    pub gamma           : bool, // Encode the linear result for the display
+    pub exposure        : f32,  // Scales the HDR color before tonemapping
+    pub bloom_threshold : f32,  // Brightness above which pixels bloom
+    pub bloom_strength  : f32,
+}
+
+impl Default for PostSettings {
+    fn default() -> Self {
+        PostSettings {
+            msaa: true,
+            fxaa: true,
+            bloom: true,
+            tonemap: true,
+            gamma: true,
+            exposure: 1.0,
+            bloom_threshold: 1.0,
+            bloom_strength: 0.6,
+        }
+    }
+}
+
+// A texture to draw into, with a depth buffer if the scene is drawn in it
+struct ColorTarget {
+    framebuffer : Framebuffer,
+    texture     : Texture,
+    _depth      : Option<Renderbuffer>,
+    width       : i32,
+    height      : i32,
+}
+
+impl ColorTarget {
+    unsafe fn new(internal_format: gl::types::GLenum, width: i32, height: i32, with_depth: bool) -> Self {
+        let texture = Texture::new(gl::TEXTURE_2D);
+        texture.bind(SCENE_UNIT);
+        gl::TexImage2D(
+            gl::TEXTURE_2D,
+            0,
+            internal_format as i32,
+            width,
+            height,
+            0,
+            gl::RGBA,
+            gl::FLOAT,
+            std::ptr::null(),
+        );
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+
+        let framebuffer = Framebuffer::new();
+        framebuffer.bind();
+        gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, texture.id(), 0);
+        let depth = if with_depth {
+            let depth = Renderbuffer::new(gl::DEPTH_COMPONENT24, width, height);
+            gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::RENDERBUFFER, depth.id());
+            Some(depth)
+        } else {
+            None
+        };
+        let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+        assert_eq!(status, gl::FRAMEBUFFER_COMPLETE, "Post-processing framebuffer is incomplete");
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+
+        ColorTarget { framebuffer, texture, _depth: depth, width, height }
+    }
+
+    unsafe fn bind(&self) {
+        self.framebuffer.bind();
+        gl::Viewport(0, 0, self.width, self.height);
+    }
+}
+
+// Multisampled color and depth, resolved into the scene target after drawing
+struct Multisampled {
+    framebuffer : Framebuffer,
+    _color      : Renderbuffer,
+    _depth      : Renderbuffer,
+}
+
+// Everything sized after the output, recreated when it changes
+struct Targets {
+    width        : i32,
+    height       : i32,
+    multisampled : Option<Multisampled>,
+    scene        : ColorTarget,
+    bloom        : [ColorTarget; 2], // Blurred back and forth between the two
+    ldr          : ColorTarget,      // The composited picture, when FXAA runs after it
+}
+
+impl Targets {
+    unsafe fn new(width: i32, height: i32, msaa: bool) -> Self {
+        let multisampled = if msaa {
+            let color = Renderbuffer::multisampled(MSAA_SAMPLES, gl::RGBA16F, width, height);
+            let depth = Renderbuffer::multisampled(MSAA_SAMPLES, gl::DEPTH_COMPONENT24, width, height);
+            let framebuffer = Framebuffer::new();
+            framebuffer.bind();
+            gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::RENDERBUFFER, color.id());
+            gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::RENDERBUFFER, depth.id());
+            let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+            assert_eq!(status, gl::FRAMEBUFFER_COMPLETE, "Multisampled framebuffer is incomplete");
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            Some(Multisampled { framebuffer, _color: color, _depth: depth })
+        } else {
+            None
+        };
+        let (bloom_width, bloom_height) = ((width / 2).max(1), (height / 2).max(1));
+        Targets {
+            width,
+            height,
+            multisampled,
+            scene: ColorTarget::new(gl::RGBA16F, width, height, !msaa),
+            bloom: [
+                ColorTarget::new(gl::RGBA16F, bloom_width, bloom_height, false),
+                ColorTarget::new(gl::RGBA16F, bloom_width, bloom_height, false),
+            ],
+            ldr: ColorTarget::new(gl::RGBA8, width, height, false),
+        }
+    }
+}
+
+pub struct PostProcess {
+    pub settings  : PostSettings,
+    targets       : Option<Targets>,
+    empty_vao     : VertexArray, // The full-screen triangle has no vertex data, but a VAO must be bound
+    bloom_extract : Shader,
+    bloom_blur    : Shader,
+    composite     : Shader,
+    fxaa          : Shader,
+}
+
+unsafe fn fullscreen_shader(fragment: &str) -> Shader {
+    ShaderBuilder::new()
+        .attach(fragment)
+        .attach("fullscreen.vert")
+        .link()
+        .unwrap_or_else(|log| panic!("{}", log))
+}
+
+unsafe fn draw_fullscreen() {
+    gl::DrawArrays(gl::TRIANGLES, 0, 3);
+}
+
+impl PostProcess {
+    pub unsafe fn new() -> Self {
+        PostProcess {
+            settings: PostSettings::default(),
+            targets: None,
+            empty_vao: VertexArray::new(),
+            bloom_extract: fullscreen_shader("bloom_extract.frag"),
+            bloom_blur: fullscreen_shader("bloom_blur.frag"),
+            composite: fullscreen_shader("composite.frag"),
+            fxaa: fullscreen_shader("fxaa.frag"),
+        }
+    }
+
+    pub unsafe fn reload_shaders(&mut self) {
+        self.bloom_extract.reload_if_changed();
+        self.bloom_blur.reload_if_changed();
+        self.composite.reload_if_changed();
+        self.fxaa.reload_if_changed();
+    }
+
+    // Prepares for a `width` by `height` picture. Returns the framebuffer to draw the scene into,
+    // then call `end`.
+    pub unsafe fn begin(&mut self, width: i32, height: i32) -> u32 {
+        let msaa = self.settings.msaa;
+        let outdated = match &self.targets {
+            Some(t) => t.width != width || t.height != height || t.multisampled.is_some() != msaa,
+            None => true,
+        };
+        if outdated {
+            self.targets = Some(Targets::new(width, height, msaa));
+        }
+        if msaa {
+            gl::Enable(gl::MULTISAMPLE);
+        } else {
+            gl::Disable(gl::MULTISAMPLE);
+        }
+
+        let targets = self.targets.as_ref().unwrap();
+        match &targets.multisampled {
+            Some(multisampled) => multisampled.framebuffer.id(),
+            None => targets.scene.framebuffer.id(),
+        }
+    }
+
+    // Runs the passes over the scene and writes the result into `framebuffer`, 0 for the window
+    pub unsafe fn end(&self, framebuffer: u32) {
+        let targets = self.targets.as_ref().expect("PostProcess::begin was not called");
+        let settings = &self.settings;
+        let (width, height) = (targets.width, targets.height);
+
+        if let Some(multisampled) = &targets.multisampled {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, multisampled.framebuffer.id());
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, targets.scene.framebuffer.id());
+            gl::BlitFramebuffer(0, 0, width, height, 0, 0, width, height, gl::COLOR_BUFFER_BIT, gl::NEAREST);
+        }
+
+        gl::Disable(gl::DEPTH_TEST);
+        self.empty_vao.bind();
+        targets.scene.texture.bind(SCENE_UNIT);
+
+        if settings.bloom {
+            targets.bloom[0].bind();
+            self.bloom_extract.activate();
+            self.bloom_extract.set_f32("threshold", settings.bloom_threshold);
+            draw_fullscreen();
+
+            self.bloom_blur.activate();
+            for pass in 0..BLOOM_BLUR_PASSES {
+                let (source, destination) = (&targets.bloom[pass % 2], &targets.bloom[1 - pass % 2]);
+                let direction = if pass % 2 == 0 {
+                    glm::vec2(1.0 / source.width as f32, 0.0)
+                } else {
+                    glm::vec2(0.0, 1.0 / source.height as f32)
+                };
+                source.texture.bind(BLOOM_UNIT);
+                destination.bind();
+                self.bloom_blur.set_vec2("direction", &direction);
+                draw_fullscreen();
+            }
+        }
+        targets.bloom[0].texture.bind(BLOOM_UNIT);
+
+        if settings.fxaa {
+            targets.ldr.bind();
+        } else {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+            gl::Viewport(0, 0, width, height);
+        }
+        self.composite.activate();
+        self.composite.set_f32("bloomStrength", if settings.bloom { settings.bloom_strength } else { 0.0 });
+        self.composite.set_f32("exposure", settings.exposure);
+        self.composite.set_bool("tonemap", settings.tonemap);
+        self.composite.set_bool("gammaCorrect", settings.gamma);
+        draw_fullscreen();
+
+        if settings.fxaa {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+            gl::Viewport(0, 0, width, height);
+            targets.ldr.texture.bind(SCENE_UNIT);
+            self.fxaa.activate();
+            self.fxaa.set_vec2("texelSize", &glm::vec2(1.0 / width as f32, 1.0 / height as f32));
+            draw_fullscreen();
+        }
+
+        gl::Enable(gl::DEPTH_TEST);
+    }
+}
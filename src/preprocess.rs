@@ -0,0 +1,196 @@
+// GLSL preprocessing done before the driver sees the source: `#include "file.glsl"` and defines
+// supplied by the program
+//
+// Included files are pasted in place, surrounded by `#line` directives so that the driver
+// reports errors with the right line. Each file gets its own source string number in those
+// directives, which is an index into `Preprocessed::files`.
+
+use std::path::{Component, Path, PathBuf};
+
+use crate::embedded_shaders;
+
+pub struct Preprocessed {
+    pub source : String,
+    pub files  : Vec<String>, // Names by source string number, the main source being 0
+}
+
+// `dir` is where includes of the main source are looked up, includes of included files are
+// relative to the file that includes them. The defines are placed right after `#version`. The
+// paths of included files are pushed to `included`, also those found before an error.
+pub fn preprocess(
+    source: &str,
+    name: &str,
+    dir: &Path,
+    defines: &[(String, String)],
+    included: &mut Vec<PathBuf>,
+) -> Result<Preprocessed, String> {
+    let mut out = Preprocessed {
+        source: String::with_capacity(source.len()),
+        files: vec![name.to_string()],
+    };
+
+    // The version directive has to come before anything else
+    let (first_line, body) = match source.lines().position(|line| !line.trim().is_empty()) {
+        Some(i) if source.lines().nth(i).unwrap().trim_start().starts_with("#version") => {
+            for line in source.lines().take(i + 1) {
+                out.source.push_str(line);
+                out.source.push('\n');
+            }
+            (i + 2, source.lines().skip(i + 1).collect::<Vec<_>>())
+        }
+        _ => (1, source.lines().collect()),
+    };
+    for (name, value) in defines {
+        out.source.push_str(&format!("#define {} {}\n", name, value));
+    }
+    out.source.push_str(&format!("#line {} 0\n", first_line));
+
+    let mut stack = vec![];
+    expand(&body, first_line, 0, dir, &mut stack, included, &mut out)?;
+    Ok(out)
+}
+
+// Appends `lines`, the first of which is line `first_line` of file `file`, expanding includes
+fn expand(
+    lines: &[&str],
+    first_line: usize,
+    file: usize,
+    dir: &Path,
+    stack: &mut Vec<PathBuf>, // Files currently being included, outermost first
+    included: &mut Vec<PathBuf>,
+    out: &mut Preprocessed,
+) -> Result<(), String> {
+    let mut in_comment = false; // Inside a block comment
+    for (i, line) in lines.iter().enumerate() {
+        let line_number = first_line + i;
+        let (code, comment_open) = strip_comments(line, in_comment);
+        in_comment = comment_open;
+        let include = match parse_include(&code) {
+            Some(include) => include,
+            None => {
+                out.source.push_str(line);
+                out.source.push('\n');
+                continue;
+            }
+        };
+        let location = format!("{}:{}", out.files[file], line_number);
+        let include = include.ok_or_else(|| format!("{}: Malformed #include, expected #include \"file\"", location))?;
+
+        let path = normalize(&dir.join(include));
+        if !included.contains(&path) {
+            included.push(path.clone());
+        }
+        if stack.contains(&path) {
+            let cycle: Vec<String> = stack.iter()
+                .skip_while(|p| **p != path)
+                .chain(std::iter::once(&path))
+                .map(|p| p.display().to_string())
+                .collect();
+            return Err(format!("{}: Include cycle {}", location, cycle.join(" -> ")));
+        }
+        // Includes missing on disk may be embedded
+        let text = embedded_shaders::read(&path)
+            .map_err(|e| format!("{}: Failed to include: {}", location, e))?;
+
+        let index = out.files.len();
+        out.files.push(path.display().to_string());
+        out.source.push_str(&format!("#line 1 {}\n", index));
+        stack.push(path.clone());
+        let lines_of_file: Vec<&str> = text.lines().collect();
+        expand(&lines_of_file, 1, index, path.parent().unwrap_or(dir), stack, included, out)?;
+        stack.pop();
+        out.source.push_str(&format!("#line {} {}\n", line_number + 1, file));
+    }
+    Ok(())
+}
+
+// The code of `line` with comments blanked out, starting inside a block comment if `in_comment`,
+// and whether a block comment is still open at the end of the line
+fn strip_comments(line: &str, mut in_comment: bool) -> (String, bool) {
+    let mut code = String::with_capacity(line.len());
+    let mut rest = line;
+    loop {
+        if in_comment {
+            match rest.find("*/") {
+                Some(end) => {
+                    rest = &rest[end + 2..];
+                    in_comment = false;
+                    code.push(' ');
+                }
+                None => return (code, true),
+            }
+        } else {
+            let line_comment = rest.find("//");
+            match rest.find("/*") {
+                Some(start) if line_comment.is_none_or(|l| start < l) => {
+                    code.push_str(&rest[..start]);
+                    rest = &rest[start + 2..];
+                    in_comment = true;
+                }
+                _ => {
+                    code.push_str(&rest[..line_comment.unwrap_or(rest.len())]);
+                    return (code, false);
+                }
+            }
+        }
+    }
+}
+
+// None if the line is not an include, Some(None) if it is one without a valid file name
+fn parse_include(line: &str) -> Option<Option<&str>> {
+    let rest = line.trim_start().strip_prefix('#')?.trim_start().strip_prefix("include")?;
+    let rest = rest.trim();
+    Some(rest.strip_prefix('"').and_then(|r| r.strip_suffix('"')).filter(|f| !f.is_empty()))
+}
+
+// Removes `.` and `..` components without touching the file system, as the file may be embedded
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if matches!(normalized.components().next_back(), Some(Component::Normal(_))) => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(source: &str, dir: &Path) -> Result<Preprocessed, String> {
+        preprocess(source, "test.vert", dir, &[], &mut vec![])
+    }
+
+    #[test]
+    fn includes_in_comments_are_left_alone() {
+        let source = "#version 430 core\n/*\n#include \"missing.glsl\"\n*/\n// #include \"missing.glsl\"\n";
+        let preprocessed = run(source, Path::new("no/such/dir")).unwrap();
+        assert!(preprocessed.source.contains("\n#include \"missing.glsl\"\n"));
+        assert_eq!(preprocessed.files.len(), 1);
+    }
+
+    #[test]
+    fn includes_after_a_closed_comment_are_expanded() {
+        let dir = std::env::temp_dir().join(format!("gloom-rs-preprocess-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("common.glsl"), "float common;\n").unwrap();
+        let source = "#version 430 core\n/* one\ntwo */ #include \"common.glsl\"\n/* three */ #include \"common.glsl\"\n";
+        let result = run(source, &dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let preprocessed = result.unwrap();
+        assert_eq!(preprocessed.source.matches("float common;").count(), 2);
+    }
+
+    #[test]
+    fn strip_comments_tracks_block_comments() {
+        assert_eq!(strip_comments("a /* b */ c // d", false), ("a   c ".to_string(), false));
+        assert_eq!(strip_comments("a /* b", false), ("a ".to_string(), true));
+        assert_eq!(strip_comments("b */ c", true), ("  c".to_string(), false));
+        assert_eq!(strip_comments("// a /* b", false), (String::new(), false));
+    }
+}
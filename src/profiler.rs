@@ -0,0 +1,253 @@
+// Where the frame time goes: named scopes timed on the CPU, and on the GPU with timestamp queries
+//
+// GPU results are only read FRAMES_IN_FLIGHT frames after they were asked for, by when the GPU is
+// done with them, so that profiling never waits for it. Those that are still not ready are left
+// out and counted. Timings are averaged over the last
+// WINDOW frames, which can also be written out as a trace for chrome://tracing or Perfetto.
+
+use std::collections::VecDeque;
+use std::path::Path;
+use std::time::Instant;
+
+use serde_json::json;
+
+const FRAMES_IN_FLIGHT: usize = 3;
+// Frames the timings are averaged and the trace kept over
+const WINDOW: usize = 120;
+
+// Queries around one scope on the GPU, a timestamp at each end
+struct GpuScope {
+    name    : &'static str,
+    queries : [u32; 2],
+    frame   : u64,
+}
+
+// The recent times of one scope, in milliseconds
+struct Timing {
+    name : &'static str,
+    cpu  : VecDeque<f32>,
+    gpu  : VecDeque<f32>,
+}
+
+struct TraceEvent {
+    name     : &'static str,
+    gpu      : bool,
+    start    : f64, // Microseconds since the profiler was created
+    duration : f64,
+    frame    : u64,
+}
+
+pub struct Profiler {
+    start         : Instant,
+    frame         : u64,
+    in_flight     : [Vec<GpuScope>; FRAMES_IN_FLIGHT + 1], // Scopes of this frame and the ones before
+    pub dropped   : u64,                                  // GPU scopes whose results were not ready
+    free_queries  : Vec<u32>,
+    open_cpu      : Vec<(&'static str, f64)>,             // Begun and not yet ended, with the start
+    open_gpu      : Vec<GpuScope>,
+    gpu_reference : (f64, i64),                           // A CPU time and the GPU clock at that moment
+    timings       : Vec<Timing>,                          // In the order they first appeared
+    events        : VecDeque<TraceEvent>,
+}
+
+fn push_sample(samples: &mut VecDeque<f32>, value: f32) {
+    if samples.len() == WINDOW {
+        samples.pop_front();
+    }
+    samples.push_back(value);
+}
+
+fn average(samples: &VecDeque<f32>) -> Option<f32> {
+    if samples.is_empty() {
+        None
+    } else {
+        Some(samples.iter().sum::<f32>() / samples.len() as f32)
+    }
+}
+
+impl Profiler {
+    pub unsafe fn new() -> Self {
+        let mut profiler = Profiler {
+            start: Instant::now(),
+            frame: 0,
+            in_flight: Default::default(),
+            dropped: 0,
+            free_queries: vec![],
+            open_cpu: vec![],
+            open_gpu: vec![],
+            gpu_reference: (0.0, 0),
+            timings: vec![],
+            events: VecDeque::new(),
+        };
+        profiler.sync_clocks();
+        profiler
+    }
+
+    fn now(&self) -> f64 {
+        self.start.elapsed().as_secs_f64() * 1e6
+    }
+
+    // Pairs the GPU clock with ours, to place GPU scopes on the same timeline
+    unsafe fn sync_clocks(&mut self) {
+        let mut gpu_time = 0;
+        gl::GetInteger64v(gl::TIMESTAMP, &mut gpu_time);
+        self.gpu_reference = (self.now(), gpu_time);
+    }
+
+    unsafe fn query(&mut self) -> u32 {
+        self.free_queries.pop().unwrap_or_else(|| {
+            let mut id = 0;
+            gl::GenQueries(1, &mut id);
+            id
+        })
+    }
+
+    fn record(&mut self, name: &'static str, gpu: bool, start: f64, duration: f64, frame: u64) {
+        let index = match self.timings.iter().position(|t| t.name == name) {
+            Some(index) => index,
+            None => {
+                self.timings.push(Timing { name, cpu: VecDeque::new(), gpu: VecDeque::new() });
+                self.timings.len() - 1
+            }
+        };
+        let timing = &mut self.timings[index];
+        push_sample(if gpu { &mut timing.gpu } else { &mut timing.cpu }, (duration / 1e3) as f32);
+        self.events.push_back(TraceEvent { name, gpu, start, duration, frame });
+    }
+
+    // Scopes nest, and end in the opposite order they began in
+    pub fn begin_cpu(&mut self, name: &'static str) {
+        let start = self.now();
+        self.open_cpu.push((name, start));
+    }
+
+    pub fn end_cpu(&mut self) {
+        let (name, start) = self.open_cpu.pop().expect("end_cpu without begin_cpu");
+        let duration = self.now() - start;
+        self.record(name, false, start, duration, self.frame);
+    }
+
+    pub unsafe fn begin_gpu(&mut self, name: &'static str) {
+        let queries = [self.query(), self.query()];
+        gl::QueryCounter(queries[0], gl::TIMESTAMP);
+        self.open_gpu.push(GpuScope { name, queries, frame: self.frame });
+    }
+
+    pub unsafe fn end_gpu(&mut self) {
+        let scope = self.open_gpu.pop().expect("end_gpu without begin_gpu");
+        gl::QueryCounter(scope.queries[1], gl::TIMESTAMP);
+        let slot = self.frame as usize % self.in_flight.len();
+        self.in_flight[slot].push(scope);
+    }
+
+    // A render pass, timed both on the CPU issuing it and on the GPU running it
+    pub unsafe fn begin_pass(&mut self, name: &'static str) {
+        self.begin_cpu(name);
+        self.begin_gpu(name);
+    }
+
+    pub unsafe fn end_pass(&mut self) {
+        self.end_gpu();
+        self.end_cpu();
+    }
+
+    // Moves on to the next frame, collecting the GPU times of the one FRAMES_IN_FLIGHT frames back
+    pub unsafe fn end_frame(&mut self) {
+        // The slot the next frame takes over, before it does
+        let slot = (self.frame as usize + 1) % self.in_flight.len();
+        self.frame += 1;
+        self.sync_clocks();
+        let (reference_time, reference_clock) = self.gpu_reference;
+        for scope in std::mem::take(&mut self.in_flight[slot]) {
+            let mut available = 0;
+            gl::GetQueryObjectiv(scope.queries[1], gl::QUERY_RESULT_AVAILABLE, &mut available);
+            // Not waited for, the scope is left out instead
+            if available != 0 {
+                let (mut begin, mut end) = (0u64, 0u64);
+                gl::GetQueryObjectui64v(scope.queries[0], gl::QUERY_RESULT, &mut begin);
+                gl::GetQueryObjectui64v(scope.queries[1], gl::QUERY_RESULT, &mut end);
+                let start = reference_time + (begin as i64 - reference_clock) as f64 / 1e3;
+                self.record(scope.name, true, start, end.saturating_sub(begin) as f64 / 1e3, scope.frame);
+            } else {
+                self.dropped += 1;
+            }
+            self.free_queries.extend_from_slice(&scope.queries);
+        }
+        while self.events.front().is_some_and(|e| e.frame + (WINDOW as u64) < self.frame) {
+            self.events.pop_front();
+        }
+    }
+
+    // Every scope seen so far with its average CPU and GPU time in milliseconds, if timed there
+    pub fn averages(&self) -> Vec<(&'static str, Option<f32>, Option<f32>)> {
+        self.timings.iter()
+            .map(|t| (t.name, average(&t.cpu), average(&t.gpu)))
+            .collect()
+    }
+
+    // Writes the scopes of the last frames in the Trace Event Format, CPU and GPU as two threads
+    pub fn write_trace(&self, path: &Path) -> Result<(), String> {
+        let thread = |tid: u32, name: &str| json!({
+            "name": "thread_name", "ph": "M", "pid": 1, "tid": tid, "args": { "name": name },
+        });
+        let mut events = vec![thread(1, "CPU"), thread(2, "GPU")];
+        events.extend(self.events.iter().map(|e| json!({
+            "name": e.name,
+            "cat": if e.gpu { "gpu" } else { "cpu" },
+            "ph": "X",
+            "ts": e.start,
+            "dur": e.duration,
+            "pid": 1,
+            "tid": if e.gpu { 2 } else { 1 },
+            "args": { "frame": e.frame },
+        })));
+        let document = json!({
+            "traceEvents": events,
+            "displayTimeUnit": "ms",
+            "otherData": { "dropped_gpu_scopes": self.dropped },
+        });
+
+        if let Some(directory) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            std::fs::create_dir_all(directory)
+                .map_err(|e| format!("Failed to create {}: {}", directory.display(), e))?;
+        }
+        let text = serde_json::to_string(&document).map_err(|e| e.to_string())?;
+        std::fs::write(path, text).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+}
+
+impl Drop for Profiler {
+    fn drop(&mut self) {
+        let mut queries = std::mem::take(&mut self.free_queries);
+        for scope in self.in_flight.iter().flatten().chain(&self.open_gpu) {
+            queries.extend_from_slice(&scope.queries);
+        }
+        unsafe { gl::DeleteQueries(queries.len() as i32, queries.as_ptr()) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless;
+
+    #[test]
+    fn gpu_scopes_are_read_frames_in_flight_frames_later() {
+        let _context = headless::test_context();
+        let mut profiler = unsafe { Profiler::new() };
+        unsafe {
+            profiler.begin_gpu("pass");
+            profiler.end_gpu();
+            gl::Finish();
+            // Ending frame 0 and the ones in flight after it
+            for _ in 0..FRAMES_IN_FLIGHT {
+                profiler.end_frame();
+                assert_eq!(profiler.averages()[..], []);
+            }
+            profiler.end_frame();
+        }
+        assert_eq!(profiler.averages().len(), 1);
+        assert!(profiler.averages()[0].2.is_some());
+        assert_eq!(profiler.dropped, 0);
+    }
+}
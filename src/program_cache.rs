@@ -0,0 +1,114 @@
+// On-disk cache of linked program binaries, so that later starts skip compiling GLSL
+//
+// Entries are keyed by a hash of the preprocessed source of every stage, which includes the
+// defines and included files, and of the driver. Binaries are only valid for the driver that
+// produced them, and even then the driver may reject one, e.g. after an update that kept the
+// version string. The caller then compiles from source as if there was no entry. When a shader is
+// reloaded after an edit, the entry of the previous version is deleted.
+
+use std::path::PathBuf;
+
+use crate::gpu::Program;
+use crate::util::get_gl_string;
+
+const CACHE_DIR: &str = "shader_cache";
+
+// 64-bit FNV-1a
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for &b in bytes {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+// `stages` holds the type and preprocessed source of each stage
+pub unsafe fn key(stages: &[(gl::types::GLenum, &str)]) -> u64 {
+    let mut hash = FNV_OFFSET_BASIS;
+    for name in [gl::VENDOR, gl::RENDERER, gl::VERSION] {
+        hash = fnv1a(hash, get_gl_string(name).as_bytes());
+        hash = fnv1a(hash, &[0]);
+    }
+    for (shader_type, source) in stages {
+        hash = fnv1a(hash, &shader_type.to_le_bytes());
+        hash = fnv1a(hash, source.as_bytes());
+        hash = fnv1a(hash, &[0]);
+    }
+    hash
+}
+
+fn path(key: u64) -> PathBuf {
+    PathBuf::from(CACHE_DIR).join(format!("{:016x}.bin", key))
+}
+
+// Whether the driver can save program binaries at all
+unsafe fn supported() -> bool {
+    let mut formats = 0;
+    gl::GetIntegerv(gl::NUM_PROGRAM_BINARY_FORMATS, &mut formats);
+    formats > 0
+}
+
+// Returns the cached program, or None if there is none or the driver rejected it
+pub unsafe fn load(key: u64) -> Option<Program> {
+    if !supported() {
+        return None;
+    }
+    let data = std::fs::read(path(key)).ok()?;
+    // The binary format comes first
+    if data.len() < 4 {
+        return None;
+    }
+    let format = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+    let binary = &data[4..];
+
+    let program = Program::new();
+    gl::ProgramBinary(program.id(), format, binary.as_ptr() as *const std::ffi::c_void, binary.len() as i32);
+    let mut success = i32::from(gl::FALSE);
+    gl::GetProgramiv(program.id(), gl::LINK_STATUS, &mut success);
+    if success != i32::from(gl::TRUE) {
+        println!("Cached program binary {} was rejected by the driver, compiling instead.", path(key).display());
+        return None;
+    }
+    Some(program)
+}
+
+// Must be called before linking for `store` to be able to retrieve the binary
+pub unsafe fn prepare(program: &Program) {
+    gl::ProgramParameteri(program.id(), gl::PROGRAM_BINARY_RETRIEVABLE_HINT, i32::from(gl::TRUE));
+}
+
+// Deletes an entry, e.g. of a program rebuilt from edited sources
+pub fn remove(key: u64) {
+    let _ = std::fs::remove_file(path(key));
+}
+
+// Saves a linked program. Failing to do so only costs a compilation next time, so it is not an error.
+pub unsafe fn store(key: u64, program: &Program) {
+    if !supported() {
+        return;
+    }
+    let mut length = 0;
+    gl::GetProgramiv(program.id(), gl::PROGRAM_BINARY_LENGTH, &mut length);
+    if length <= 0 {
+        return;
+    }
+    let mut binary = vec![0u8; length as usize];
+    let mut format = 0;
+    gl::GetProgramBinary(
+        program.id(),
+        length,
+        std::ptr::null_mut(),
+        &mut format,
+        binary.as_mut_ptr() as *mut std::ffi::c_void,
+    );
+
+    let mut data = format.to_le_bytes().to_vec();
+    data.extend_from_slice(&binary);
+    let result = std::fs::create_dir_all(CACHE_DIR).and_then(|_| std::fs::write(path(key), data));
+    if let Err(e) = result {
+        println!("WARNING: Failed to cache program binary: {}", e);
+    }
+}
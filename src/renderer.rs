@@ -0,0 +1,385 @@
+// Draws a scene from a camera into a framebuffer: the shadow pass followed by the lit pass
+
+use crate::camera::Camera;
+use crate::debug_draw::DebugDraw;
+use crate::frame_uniforms::{self, FrameData, FrameUniforms, MAX_LIGHTS};
+use crate::gpu::GpuMesh;
+use crate::light;
+use crate::postprocess::PostProcess;
+use crate::profiler::Profiler;
+use crate::scene::Scene;
+use crate::scene_graph::SceneNode;
+use crate::shader::Shader;
+use crate::shadow::ShadowMap;
+use crate::text::TextRenderer;
+use crate::util;
+
+// Shadows from the moon: size of the depth texture, radius of the region around the origin the
+// helicopters fly in that gets shadows, depth offset against acne and filter radius in texels
+const SHADOW_MAP_RESOLUTION: i32 = 2048;
+const SHADOW_REGION_RADIUS: f32 = 80.0;
+const SHADOW_BIAS: f32 = 0.002;
+const SHADOW_PCF_RADIUS: i32 = 1;
+
+// Night sky, as a color for the display like the vertex colors. Lighting happens in linear space,
+// so it is converted before clearing.
+const SKY_COLOR: [f32; 3] = [0.035, 0.046, 0.078];
+
+// What the scene is drawn as
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ViewMode {
+    Shaded,
+    Normals, // World space normals as colors
+    Depth,   // Distance from the camera, white up close
+}
+
+impl ViewMode {
+    pub fn next(self) -> Self {
+        match self {
+            ViewMode::Shaded => ViewMode::Normals,
+            ViewMode::Normals => ViewMode::Depth,
+            ViewMode::Depth => ViewMode::Shaded,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ViewMode::Shaded => "Shaded",
+            ViewMode::Normals => "Normals",
+            ViewMode::Depth => "Depth",
+        }
+    }
+}
+
+// What the last frame took, for the HUD
+#[derive(Clone, Copy, Default)]
+pub struct RenderStats {
+    pub draw_calls   : u32, // Meshes drawn, over all passes
+    pub culled_nodes : u32, // Nodes with geometry that were outside the view of the camera
+}
+
+pub struct Renderer {
+    pub post            : PostProcess,
+    pub debug           : DebugDraw,    // Queue debug shapes here, they are drawn with the next frame
+    pub text            : TextRenderer, // Queue text here, it is drawn over the next frame
+    pub stats           : RenderStats,  // Of the last frame
+    pub profiler        : Profiler,     // Times the passes, add scopes of your own around them
+    pub view_mode       : ViewMode,
+    pub wireframe       : bool,         // Draw the edges of the triangles over the scene
+    pub normal_lines    : bool,         // Draw the vertex normals as short lines
+    simple_shader       : Shader,
+    debug_view_shader   : Shader,
+    wireframe_shader    : Shader,
+    normal_lines_shader : Shader,
+    shadow_shader       : Shader,
+    frame_uniforms      : FrameUniforms,
+    shadow_map          : ShadowMap,
+}
+
+// Whether a sphere lies entirely outside one of the planes of the view frustum. The planes come
+// from the rows of the view projection matrix and are normalized, so that the signed distance can
+// be compared to the radius.
+fn outside_frustum(view_projection: &glm::Mat4, center: &glm::Vec3, radius: f32) -> bool {
+    let row = |i: usize| view_projection.row(i).transpose();
+    let w = row(3);
+    let planes = [w + row(0), w - row(0), w + row(1), w - row(1), w + row(2), w - row(2)];
+    planes.iter().any(|plane| {
+        let normal = plane.xyz();
+        (normal.dot(center) + plane.w) / normal.norm() < -radius
+    })
+}
+
+// A mesh to draw and where, with its depth in front of the camera to sort by
+struct DrawItem<'a> {
+    mesh      : &'a GpuMesh,
+    transform : glm::Mat4,
+    depth     : f32, // Of the center of the bounding sphere
+}
+
+// Gathers the meshes below `node` at the level of detail they are seen at. `lod_scale` converts a
+// size at a view depth of one unit into pixels on screen, i.e. half the viewport height times the
+// focal length of the projection. With `cull`, nodes outside the view are left out, which must
+// not happen in passes seen from somewhere else than the camera.
+fn collect_draws<'a>(node: &'a SceneNode,
+                     view_projection_matrix: &glm::Mat4,
+                     transformation_so_far: &glm::Mat4,
+                     lod_scale: f32,
+                     cull: bool,
+                     stats: &mut RenderStats,
+                     items: &mut Vec<DrawItem<'a>>) {
+
+    let current_transform = transformation_so_far * node.local_transform();
+
+    // Draw this node if it has geometry
+    if node.mesh.is_some() {
+        let world_center = (current_transform * node.bounding_center.push(1.0)).xyz();
+        let world_radius = node.bounding_radius * (0..3)
+            .map(|i| current_transform.column(i).xyz().norm())
+            .fold(0.0, f32::max);
+
+        // Nodes without a bounding sphere are always drawn
+        if cull && world_radius > 0.0 && outside_frustum(view_projection_matrix, &world_center, world_radius) {
+            stats.culled_nodes += 1;
+        } else {
+            // Pick a level of detail from how large the bounding sphere appears on screen. The w
+            // of the projected center is its depth in front of the camera.
+            let center = view_projection_matrix * world_center.push(1.0);
+            let screen_size = if center.w > 0.0 {
+                2.0 * world_radius * lod_scale / center.w
+            } else {
+                f32::MAX
+            };
+            if let Some(mesh) = node.select_lod(screen_size) {
+                items.push(DrawItem { mesh, transform: current_transform, depth: center.w });
+            }
+        }
+    }
+
+    // Recurse to children with the accumulated transformation
+    for &child in &node.children {
+        if !child.is_null() {
+            collect_draws(unsafe { &*child }, view_projection_matrix, &current_transform, lod_scale, cull, stats, items);
+        }
+    }
+}
+
+unsafe fn draw_items(items: &[DrawItem], shader: &Shader, stats: &mut RenderStats) {
+    for item in items {
+        //Passes the matrix to shader
+        shader.set_mat4("modelMatrix", &item.transform);
+        item.mesh.draw();
+        stats.draw_calls += 1;
+    }
+}
+
+// Sets the state everything is drawn with and prints what the context runs on
+pub unsafe fn init_gl() {
+    gl::Enable(gl::DEPTH_TEST);
+    gl::DepthFunc(gl::LESS);
+    gl::Disable(gl::CULL_FACE);
+    gl::Disable(gl::MULTISAMPLE);
+    // Blending is only enabled around what is drawn see-through
+    gl::Disable(gl::BLEND);
+    gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+    gl::Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
+    gl::DebugMessageCallback(Some(util::debug_callback), std::ptr::null());
+
+    // Print some diagnostics
+    println!(
+        "{}: {}",
+        util::get_gl_string(gl::VENDOR),
+        util::get_gl_string(gl::RENDERER)
+    );
+    println!("OpenGL\t: {}", util::get_gl_string(gl::VERSION));
+    println!(
+        "GLSL\t: {}",
+        util::get_gl_string(gl::SHADING_LANGUAGE_VERSION)
+    );
+}
+
+impl Renderer {
+    // Shaders are embedded in the binary, files in shaders/ relative to the working directory
+    // override them.
+    pub unsafe fn new() -> Self {
+        let simple_shader = frame_uniforms::shader_builder()
+            .attach("simple.frag")
+            .attach("simple.vert")
+            .link()
+            .unwrap_or_else(|log| panic!("{}", log));
+
+        let debug_view_shader = frame_uniforms::shader_builder()
+            .attach("debug_view.frag")
+            .attach("simple.vert")
+            .link()
+            .unwrap_or_else(|log| panic!("{}", log));
+
+        let wireframe_shader = frame_uniforms::shader_builder()
+            .attach("wireframe.frag")
+            .attach("simple.vert")
+            .link()
+            .unwrap_or_else(|log| panic!("{}", log));
+
+        let normal_lines_shader = frame_uniforms::shader_builder()
+            .attach("debug_lines.frag")
+            .attach("normal_lines.geom")
+            .attach("normal_lines.vert")
+            .link()
+            .unwrap_or_else(|log| panic!("{}", log));
+
+        let shadow_shader = frame_uniforms::shader_builder()
+            .attach("shadow_depth.frag")
+            .attach("shadow_depth.vert")
+            .link()
+            .unwrap_or_else(|log| panic!("{}", log));
+
+        let frame_uniforms = FrameUniforms::new();
+        let mut shadow_map = ShadowMap::new(SHADOW_MAP_RESOLUTION, glm::zero(), SHADOW_REGION_RADIUS);
+        shadow_map.bias = SHADOW_BIAS;
+        shadow_map.pcf_radius = SHADOW_PCF_RADIUS;
+
+        Renderer {
+            post: PostProcess::new(),
+            debug: DebugDraw::new(),
+            text: TextRenderer::new(),
+            stats: RenderStats::default(),
+            profiler: Profiler::new(),
+            view_mode: ViewMode::Shaded,
+            wireframe: false,
+            normal_lines: false,
+            simple_shader,
+            debug_view_shader,
+            wireframe_shader,
+            normal_lines_shader,
+            shadow_shader,
+            frame_uniforms,
+            shadow_map,
+        }
+    }
+
+    // Picks up edits to the shader files
+    pub unsafe fn reload_shaders(&mut self) {
+        self.simple_shader.reload_if_changed();
+        self.debug_view_shader.reload_if_changed();
+        self.wireframe_shader.reload_if_changed();
+        self.normal_lines_shader.reload_if_changed();
+        self.shadow_shader.reload_if_changed();
+        self.post.reload_shaders();
+        self.debug.reload_shader();
+        self.text.reload_shader();
+    }
+
+    // Draws `scene` as posed at `time` into `framebuffer`, 0 being the window, which is
+    // `width` by `height` pixels. The queued text goes on top in `finish_frame`, so that what
+    // is in `framebuffer` in between is the scene alone.
+    pub unsafe fn render(&mut self, scene: &Scene, camera: &Camera, time: f32, framebuffer: u32, width: i32, height: i32) {
+        // A minimized window is 0 by 0, which no render target can be
+        let (width, height) = (width.max(1), height.max(1));
+        // The scene goes into the HDR framebuffer of the post-processing, which then writes to
+        // `framebuffer`
+        let scene_framebuffer = self.post.begin(width, height);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, scene_framebuffer);
+        gl::Viewport(0, 0, width, height);
+
+        // Clear the color and depth buffers
+        let sky = SKY_COLOR.map(|c| c.powf(2.2));
+        gl::ClearColor(sky[0], sky[1], sky[2], 1.0);
+        gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+
+        let view = camera.view();
+        let projection = camera.projection(width as f32 / height as f32);
+        let view_projection = projection * view;
+        let lod_scale = projection[(1, 1)] * height as f32 / 2.0;
+
+        let mut stats = RenderStats::default();
+
+        //Passing the camera, time and lights to every shader at once, now that the nodes
+        //carrying lights are in place
+        let mut lights = vec![];
+        light::collect_lights(&scene.root, &glm::Mat4::identity(), &mut lights);
+        let mut frame_data = FrameData::new(&view, &projection, time, &lights);
+        let sun = lights.iter().take(MAX_LIGHTS).position(|l| l.is_directional());
+        if let Some(sun) = sun {
+            let direction = glm::make_vec3(&lights[sun].direction[..3]);
+            frame_data.set_shadow(sun, &self.shadow_map.light_matrix(&direction));
+        }
+        self.frame_uniforms.update(&frame_data);
+
+        // Depth from the sun first, then the scene as seen from the camera. Both passes
+        // pick levels of detail from the camera, so the shadows match what is drawn.
+        if sun.is_some() {
+            self.profiler.begin_pass("shadows");
+            self.shadow_map.begin();
+            self.shadow_shader.activate();
+            let mut casters = vec![];
+            collect_draws(&scene.root, &view_projection, &glm::Mat4::identity(), lod_scale, false, &mut stats, &mut casters);
+            draw_items(&casters, &self.shadow_shader, &mut stats);
+            self.shadow_map.end(scene_framebuffer, width, height);
+            self.profiler.end_pass();
+        }
+        self.profiler.begin_pass("scene");
+        // Opaque meshes front to back, so that the depth test spares hidden fragments, then the
+        // transparent ones back to front over them. Those test against the depth buffer but leave
+        // it alone, so that what is behind them in the same pass still shows through.
+        self.profiler.begin_cpu("traversal");
+        let mut items = vec![];
+        collect_draws(&scene.root, &view_projection, &glm::Mat4::identity(), lod_scale, true, &mut stats, &mut items);
+        let (mut opaque, mut transparent): (Vec<DrawItem>, Vec<DrawItem>) =
+            items.into_iter().partition(|item| !item.mesh.transparent);
+        opaque.sort_by(|a, b| a.depth.total_cmp(&b.depth));
+        transparent.sort_by(|a, b| b.depth.total_cmp(&a.depth));
+        self.profiler.end_cpu();
+
+        let shader = match self.view_mode {
+            ViewMode::Shaded => {
+                self.simple_shader.activate();
+                self.simple_shader.set_f32("shadowBias", self.shadow_map.bias);
+                self.simple_shader.set_i32("shadowPcfRadius", self.shadow_map.pcf_radius);
+                &self.simple_shader
+            }
+            ViewMode::Normals | ViewMode::Depth => {
+                self.debug_view_shader.activate();
+                self.debug_view_shader.set_i32("mode", if self.view_mode == ViewMode::Normals { 1 } else { 2 });
+                &self.debug_view_shader
+            }
+        };
+        draw_items(&opaque, shader, &mut stats);
+        gl::DepthMask(gl::FALSE);
+        gl::Enable(gl::BLEND);
+        draw_items(&transparent, shader, &mut stats);
+        gl::Disable(gl::BLEND);
+        gl::DepthMask(gl::TRUE);
+        self.profiler.end_pass();
+
+        self.profiler.begin_pass("overlays");
+        if self.wireframe {
+            // Pulled towards the camera so that the lines win over the faces they lie on
+            self.wireframe_shader.activate();
+            gl::PolygonMode(gl::FRONT_AND_BACK, gl::LINE);
+            gl::Enable(gl::POLYGON_OFFSET_LINE);
+            gl::PolygonOffset(-1.0, -1.0);
+            draw_items(&opaque, &self.wireframe_shader, &mut stats);
+            draw_items(&transparent, &self.wireframe_shader, &mut stats);
+            gl::Disable(gl::POLYGON_OFFSET_LINE);
+            gl::PolygonMode(gl::FRONT_AND_BACK, gl::FILL);
+        }
+        if self.normal_lines {
+            self.normal_lines_shader.activate();
+            draw_items(&opaque, &self.normal_lines_shader, &mut stats);
+            draw_items(&transparent, &self.normal_lines_shader, &mut stats);
+        }
+        self.stats = stats;
+
+        if self.debug.show_scene {
+            self.debug.scene_nodes(&scene.root, &glm::Mat4::identity());
+            scene.draw_flight_paths(&mut self.debug, time);
+        }
+        self.debug.flush();
+        self.profiler.end_pass();
+
+        self.profiler.begin_pass("post");
+
+        // The debug views are colors for the display as they are, so they skip the tonemapping,
+        // bloom and gamma correction
+        if self.view_mode == ViewMode::Shaded {
+            self.post.end(framebuffer);
+        } else {
+            let settings = self.post.settings;
+            self.post.settings.tonemap = false;
+            self.post.settings.bloom = false;
+            self.post.settings.gamma = false;
+            self.post.end(framebuffer);
+            self.post.settings = settings;
+        }
+        self.profiler.end_pass();
+    }
+
+    // Draws the queued text over the frame `render` left in the current framebuffer
+    pub unsafe fn finish_frame(&mut self, width: i32, height: i32) {
+        let (width, height) = (width.max(1), height.max(1));
+        self.profiler.begin_pass("text");
+        self.text.flush(width, height);
+        self.profiler.end_pass();
+
+        self.profiler.end_frame();
+    }
+}
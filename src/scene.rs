@@ -0,0 +1,341 @@
+// The lunar landscape with helicopters flying over it, and optionally a glTF model
+//
+// Loading and animating the scene is kept apart from the window, so that the same scene can be
+// drawn on screen or offscreen by the headless renderer.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+
+use glm::vec3;
+
+use crate::debug_draw::{self, DebugDraw};
+use crate::gltf_import;
+use crate::gpu::GpuMesh;
+use crate::light::Light;
+use crate::mesh::{self, HeightGrid, Helicopter, Mesh};
+use crate::scene_file;
+use crate::scene_graph::{self, Node, SceneNode};
+use crate::toolbox::{simple_heading_animation, Heading};
+use crate::vertex_layout::VertexLayout;
+
+// How many coarser levels of detail to generate per mesh, and the projected size in pixels below
+// which each of them takes over
+const LOD_SCREEN_SIZES: [f32; 3] = [250.0, 100.0, 40.0];
+// Fraction of the triangles kept from one level to the next
+const LOD_REDUCTION: f32 = 0.35;
+
+const HELICOPTER_COUNT: usize = 5;
+
+// How long simple_heading_animation takes to fly a whole circuit, in seconds
+const CIRCUIT_DURATION: f32 = std::f32::consts::TAU / 0.8;
+
+// What drives the animation of the helicopters, tweakable in the editor
+#[derive(Clone, Copy)]
+pub struct AnimationParams {
+    pub main_rotor_speed : f32, // Radians per second
+    pub tail_rotor_speed : f32,
+    pub spacing          : f32, // Seconds each helicopter flies ahead of the one before it
+    pub beacon_flash     : f32, // Seconds the beacons are lit, once every second
+}
+
+impl Default for AnimationParams {
+    fn default() -> Self {
+        AnimationParams {
+            main_rotor_speed: 10.0,
+            tail_rotor_speed: 20.0,
+            spacing: 1.2,
+            beacon_flash: 0.15,
+        }
+    }
+}
+
+// Which components of the transformation of a node the animation sets every frame, overriding
+// whatever the editor changes them to
+#[derive(Clone, Copy, Default)]
+pub struct Animated {
+    pub position : [bool; 3],
+    pub rotation : [bool; 3],
+    pub scale    : [bool; 3],
+}
+
+pub struct Scene {
+    pub root       : Node,           // Carries the moonlight, with the terrain below it
+    pub terrain    : Mesh,
+    ground         : HeightGrid,     // The terrain, for finding the height of the ground
+    pub animation  : AnimationParams,
+    helicopter     : Helicopter,
+    gltf_model     : Option<(gltf_import::Model, Vec<Rc<GpuMesh>>)>,
+    gltf_instance  : Option<gltf_import::Instance>,
+    uploaded       : [Rc<GpuMesh>; 5], // The terrain, body, door, main and tail rotor
+    helicopters    : Vec<Node>,
+    bodies         : Vec<Node>,
+    main_rotors    : Vec<Node>,
+    tail_rotors    : Vec<Node>,
+}
+
+// Uploads successively coarser versions of the mesh, each paired with the screen size below which
+// it replaces the one before it
+unsafe fn upload_lods(mesh: &Mesh, layout: &VertexLayout) -> Vec<scene_graph::Lod> {
+    mesh.lod_chain(LOD_SCREEN_SIZES.len(), LOD_REDUCTION)
+        .iter()
+        .zip(LOD_SCREEN_SIZES.iter())
+        .map(|(lod, &max_screen_size)| scene_graph::Lod {
+            mesh: Rc::new(lod.upload(layout)),
+            max_screen_size,
+        })
+        .collect()
+}
+
+impl Scene {
+    // Loads lunarsurface.obj and helicopter.obj from `resources`, and the glTF model if any, then
+    // applies the tweaks saved in `scene_path` if given
+    pub unsafe fn load(resources: &Path,
+                       gltf_path: Option<&str>,
+                       scene_path: Option<&Path>,
+                       layout: &VertexLayout) -> Scene {
+        let terrain_path = resources.join("lunarsurface.obj");
+        let helicopter_path = resources.join("helicopter.obj");
+        for path in [&terrain_path, &helicopter_path] {
+            if !path.is_file() {
+                panic!("Missing {}, pass the directory holding the models with --resources", path.display());
+            }
+        }
+
+        let terrain = mesh::Terrain::load(&terrain_path.to_string_lossy());
+        let helicopter = Helicopter::load(&helicopter_path.to_string_lossy());
+        let gltf_model = gltf_path.map(gltf_import::Model::load);
+        let mut scene = Scene::new(terrain, helicopter, gltf_model, layout);
+        if let Some(path) = scene_path {
+            scene_file::load(&mut scene.root, &mut scene.animation, path).unwrap_or_else(|e| panic!("{}", e));
+        }
+        scene
+    }
+
+    // Uploads the meshes in `layout` and builds the scene graph
+    pub unsafe fn new(terrain: Mesh,
+                      helicopter: Helicopter,
+                      gltf_model: Option<gltf_import::Model>,
+                      layout: &VertexLayout) -> Scene {
+        let terrain_gpu = Rc::new(terrain.upload(layout));
+        let body_gpu = Rc::new(helicopter.body.upload(layout));
+        let door_gpu = Rc::new(helicopter.door.upload(layout));
+        let main_rotor_gpu = Rc::new(helicopter.main_rotor.upload(layout));
+        let tail_rotor_gpu = Rc::new(helicopter.tail_rotor.upload(layout));
+
+        // Meshes of the optional glTF model, uploaded once and shared by every instance
+        let gltf_model = gltf_model.map(|model| {
+            let gpu_meshes: Vec<Rc<GpuMesh>> = model.meshes.iter()
+                .map(|m| Rc::new(m.upload(layout)))
+                .collect();
+            (model, gpu_meshes)
+        });
+
+        let uploaded = [
+            terrain_gpu.clone(),
+            body_gpu.clone(),
+            door_gpu.clone(),
+            main_rotor_gpu.clone(),
+            tail_rotor_gpu.clone(),
+        ];
+
+        // Simplified versions of every mesh, for when they are far away
+        println!("Generating levels of detail...");
+        let before = std::time::Instant::now();
+        let body_lods = upload_lods(&helicopter.body, layout);
+        let door_lods = upload_lods(&helicopter.door, layout);
+        let main_rotor_lods = upload_lods(&helicopter.main_rotor, layout);
+        let tail_rotor_lods = upload_lods(&helicopter.tail_rotor, layout);
+        let terrain_lods = upload_lods(&terrain, layout);
+        println!("Done in {:.3}ms.", before.elapsed().as_micros() as f32 / 1e3);
+
+        // Creates one terrain node as root node
+        let mut root = SceneNode::new();
+        root.name = "Root".to_string();
+        let mut terrain_node = SceneNode::from_mesh(terrain_gpu);
+        terrain_node.name = "Terrain".to_string();
+        terrain_node.set_lods(&terrain_lods, terrain.bounding_sphere());
+        root.add_child(&terrain_node);
+
+        // Moonlight
+        root.lights.push(Light::directional(vec3(0.8, -0.5, 0.6), vec3(0.75, 0.8, 1.0), 0.15));
+
+        let gltf_instance = gltf_model.as_ref().map(|(model, gpu_meshes)| {
+            let instance = model.instantiate(gpu_meshes);
+            terrain_node.add_child(&instance.root);
+            if let Some(animation) = model.animations.first() {
+                println!("Playing animation {} ({:.2}s)", animation.name, animation.duration);
+            }
+            instance
+        });
+
+        let mut helicopters: Vec<Node> = Vec::new();
+        let mut bodies: Vec<Node> = Vec::new();
+        let mut main_rotors: Vec<Node> = Vec::new();
+        let mut tail_rotors: Vec<Node> = Vec::new();
+
+        // Creates the helicopters, that all have the same terrain node as parent
+        for i in 0..HELICOPTER_COUNT {
+            let mut helicopter_root_node = SceneNode::new();
+            helicopter_root_node.name = format!("Helicopter {}", i + 1);
+
+            let mut body_node = SceneNode::from_mesh(body_gpu.clone());
+            let mut door_node = SceneNode::from_mesh(door_gpu.clone());
+            let mut main_rotor_node = SceneNode::from_mesh(main_rotor_gpu.clone());
+            let mut tail_rotor_node = SceneNode::from_mesh(tail_rotor_gpu.clone());
+            body_node.name = "Body".to_string();
+            door_node.name = "Door".to_string();
+            main_rotor_node.name = "Main rotor".to_string();
+            tail_rotor_node.name = "Tail rotor".to_string();
+
+            body_node.set_lods(&body_lods, helicopter.body.bounding_sphere());
+            door_node.set_lods(&door_lods, helicopter.door.bounding_sphere());
+            main_rotor_node.set_lods(&main_rotor_lods, helicopter.main_rotor.bounding_sphere());
+            tail_rotor_node.set_lods(&tail_rotor_lods, helicopter.tail_rotor.bounding_sphere());
+
+            // A searchlight under the nose pointing ahead and down, and a red beacon on the tail.
+            // The nose points along -z.
+            body_node.lights.push(Light::spot(
+                vec3(0.0, 0.5, -4.0),
+                vec3(0.0, -1.0, -1.2),
+                0.2, 0.35,
+                vec3(1.0, 0.95, 0.8), 60.0, 80.0,
+            ));
+            body_node.lights.push(Light::point(vec3(0.0, 3.0, 9.5), vec3(1.0, 0.1, 0.05), 4.0, 15.0));
+
+            tail_rotor_node.reference_point = vec3(0.35, 2.3, 10.4);
+            main_rotor_node.reference_point = vec3(0.0, 0.0, 0.0);
+
+            terrain_node.add_child(&helicopter_root_node);
+            helicopter_root_node.add_child(&body_node);
+            body_node.add_child(&door_node);
+            body_node.add_child(&main_rotor_node);
+            body_node.add_child(&tail_rotor_node);
+
+            helicopters.push(helicopter_root_node);
+            bodies.push(body_node);
+            main_rotors.push(main_rotor_node);
+            tail_rotors.push(tail_rotor_node);
+        }
+
+        Scene {
+            root,
+            ground: HeightGrid::new(&terrain),
+            terrain,
+            animation: AnimationParams::default(),
+            helicopter,
+            gltf_model,
+            gltf_instance,
+            uploaded,
+            helicopters,
+            bodies,
+            main_rotors,
+            tail_rotors,
+        }
+    }
+
+    // Poses everything for `time` seconds into the simulation
+    pub fn animate(&mut self, time: f32) {
+        for (i, heli) in self.helicopters.iter_mut().enumerate() {
+            let offsets = self.animation.spacing * i as f32;
+            let heading = simple_heading_animation(time + offsets);
+
+            heli.position.x = heading.x;
+            heli.position.z = heading.z;
+            heli.rotation.x = heading.roll;
+            heli.rotation.y = heading.yaw;
+            heli.rotation.z = heading.pitch;
+
+            self.main_rotors[i].rotation.y = time * self.animation.main_rotor_speed;
+            self.tail_rotors[i].rotation.x = time * self.animation.tail_rotor_speed;
+
+            // Blink the beacon, the helicopters out of step
+            self.bodies[i].lights[1].enabled = (time + offsets).fract() < self.animation.beacon_flash;
+        }
+        if let (Some((model, _)), Some(instance)) = (&self.gltf_model, &mut self.gltf_instance) {
+            if let Some(animation) = model.animations.first() {
+                animation.apply(instance, time);
+            }
+        }
+    }
+
+    // The nodes posed by `animate`, by address, with what it sets of each
+    pub fn animated_nodes(&self) -> HashMap<*const SceneNode, Animated> {
+        let mut animated: HashMap<*const SceneNode, Animated> = HashMap::new();
+        let address = |node: &Node| &***node as *const SceneNode;
+        for heli in &self.helicopters {
+            let entry = animated.entry(address(heli)).or_default();
+            entry.position = [true, false, true];
+            entry.rotation = [true; 3];
+        }
+        for rotor in &self.main_rotors {
+            animated.entry(address(rotor)).or_default().rotation[1] = true;
+        }
+        for rotor in &self.tail_rotors {
+            animated.entry(address(rotor)).or_default().rotation[0] = true;
+        }
+        if let (Some((model, _)), Some(instance)) = (&self.gltf_model, &self.gltf_instance) {
+            for channel in model.animations.first().iter().flat_map(|a| &a.channels) {
+                let entry = animated.entry(address(&instance.nodes[channel.node])).or_default();
+                match channel.keyframes {
+                    gltf_import::Keyframes::Translation(_) => entry.position = [true; 3],
+                    gltf_import::Keyframes::Rotation(_) => entry.rotation = [true; 3],
+                    gltf_import::Keyframes::Scale(_) => entry.scale = [true; 3],
+                }
+            }
+        }
+        animated
+    }
+
+    pub fn helicopter_count(&self) -> usize {
+        self.helicopters.len()
+    }
+
+    // Where helicopter `index` is and how it is turned at `time`
+    pub fn helicopter_heading(&self, index: usize, time: f32) -> Heading {
+        simple_heading_animation(time + self.animation.spacing * index as f32)
+    }
+
+    // Height of the ground under `x`, `z`, the terrain being placed at the origin
+    pub fn ground_height(&self, x: f32, z: f32) -> Option<f32> {
+        self.ground.height_at(x, z)
+    }
+
+    // The path of every helicopter over a whole circuit, with an arrow from where it is now along
+    // where it is heading
+    pub fn draw_flight_paths(&self, debug: &mut DebugDraw, time: f32) {
+        let at = |t: f32| {
+            let heading = simple_heading_animation(t);
+            glm::vec3(heading.x, 0.0, heading.z)
+        };
+        for i in 0..self.helicopters.len() {
+            let start = time + self.animation.spacing * i as f32;
+            let points: Vec<glm::Vec3> = (0..=128)
+                .map(|k| at(start + CIRCUIT_DURATION * k as f32 / 128.0))
+                .collect();
+            debug.path(&points, debug_draw::CYAN);
+            let position = at(start);
+            let ahead = at(start + 0.5) - position;
+            debug.arrow(&position, &(position + ahead.normalize() * 6.0), debug_draw::YELLOW);
+        }
+    }
+
+    // The CPU side of each uploaded mesh by the address of the uploaded one, for exporting the scene
+    pub fn mesh_library(&self) -> HashMap<*const GpuMesh, &Mesh> {
+        let meshes = [
+            &self.terrain,
+            &self.helicopter.body,
+            &self.helicopter.door,
+            &self.helicopter.main_rotor,
+            &self.helicopter.tail_rotor,
+        ];
+        let mut library: HashMap<*const GpuMesh, &Mesh> = self.uploaded.iter()
+            .map(Rc::as_ptr)
+            .zip(meshes)
+            .collect();
+        if let Some((model, gpu_meshes)) = &self.gltf_model {
+            library.extend(gpu_meshes.iter().map(Rc::as_ptr).zip(model.meshes.iter()));
+        }
+        library
+    }
+}
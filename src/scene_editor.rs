@@ -0,0 +1,59 @@
+// The editor panel: the scene graph as a tree with the transformation of every node, and the
+// parameters of the animation, editable while the scene runs
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::gui::Ui;
+use crate::scene::{Animated, Scene};
+use crate::scene_file;
+use crate::scene_graph::SceneNode;
+
+fn node_tree(ui: &mut Ui, node: &mut SceneNode, path: &str, animated: &HashMap<*const SceneNode, Animated>) {
+    let name = if node.name.is_empty() { "Node" } else { &node.name };
+    let label = match node.children.len() {
+        0 => name.to_string(),
+        n => format!("{} ({})", name, n),
+    };
+    if ui.tree_node(&format!("node {}", path), &label) {
+        // What the animation sets is greyed out, as it would undo any change every frame
+        let locked = animated.get(&(node as *const SceneNode)).copied().unwrap_or_default();
+        ui.drag_vec3_locked(&format!("{}/position", path), "Position", &mut node.position, locked.position, 0.05);
+        ui.drag_vec3_locked(&format!("{}/rotation", path), "Rotation", &mut node.rotation, locked.rotation, 0.01);
+        ui.drag_vec3_locked(&format!("{}/scale", path), "Scale", &mut node.scale, locked.scale, 0.01);
+        ui.drag_vec3(&format!("{}/reference", path), "Reference", &mut node.reference_point, 0.05);
+        let children = node.children.clone();
+        for (i, child) in children.into_iter().enumerate() {
+            if !child.is_null() {
+                node_tree(ui, unsafe { &mut *child }, &scene_file::child_path(path, i), animated);
+            }
+        }
+        ui.tree_pop();
+    }
+}
+
+// Declares the panel for this frame. The save button writes the scene file to `save_path`.
+pub fn panel(ui: &mut Ui, scene: &mut Scene, save_path: &Path) {
+    ui.label("Scene editor, F8 to close");
+    ui.label("Drag values sideways to change them");
+    ui.label("Grey values follow the animation");
+    if ui.button(&format!("Save to {}", save_path.display())) {
+        match scene_file::save(&mut scene.root, &scene.animation, save_path) {
+            Ok(()) => println!("Saved scene to {}", save_path.display()),
+            Err(e) => println!("Failed to save scene: {}", e),
+        }
+    }
+
+    if ui.tree_node("animation", "Animation") {
+        let animation = &mut scene.animation;
+        ui.drag_f32("animation/main_rotor", "Main rotor", &mut animation.main_rotor_speed, 0.05);
+        ui.drag_f32("animation/tail_rotor", "Tail rotor", &mut animation.tail_rotor_speed, 0.05);
+        ui.drag_f32("animation/spacing", "Spacing", &mut animation.spacing, 0.01);
+        ui.drag_f32("animation/beacon", "Beacon flash", &mut animation.beacon_flash, 0.002);
+        animation.beacon_flash = animation.beacon_flash.clamp(0.0, 1.0);
+        ui.tree_pop();
+    }
+
+    let animated = scene.animated_nodes();
+    node_tree(ui, &mut scene.root, "", &animated);
+}
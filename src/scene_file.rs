@@ -0,0 +1,195 @@
+// Scene files: the tweaks made in the editor, saved as JSON and applied over the scene built in
+// code on the next start
+//
+// Every node is found again by its path from the root, the child indices joined by slashes with
+// the root being "". The names are only written to make the file readable. Nodes posed by the
+// animation are saved too, but the animation overrides what it drives.
+
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use crate::scene::AnimationParams;
+use crate::scene_graph::SceneNode;
+
+pub fn child_path(path: &str, index: usize) -> String {
+    if path.is_empty() { index.to_string() } else { format!("{}/{}", path, index) }
+}
+
+// Calls `f` with every node below and including `node`, and its path
+pub fn visit_nodes(node: &mut SceneNode, path: &str, f: &mut impl FnMut(&mut SceneNode, &str)) {
+    f(node, path);
+    let children = node.children.clone();
+    for (i, child) in children.into_iter().enumerate() {
+        if !child.is_null() {
+            visit_nodes(unsafe { &mut *child }, &child_path(path, i), f);
+        }
+    }
+}
+
+// Through the shortest decimal of the f32, so that 0.15 is not written as 0.15000000596046448
+fn number(v: f32) -> Value {
+    json!(v.to_string().parse::<f64>().unwrap_or_default())
+}
+
+fn vec3_json(v: &glm::Vec3) -> Value {
+    json!([number(v.x), number(v.y), number(v.z)])
+}
+
+fn vec3_from_json(value: &Value) -> Option<glm::Vec3> {
+    let array = value.as_array()?;
+    match array[..] {
+        [ref x, ref y, ref z] => Some(glm::vec3(x.as_f64()? as f32, y.as_f64()? as f32, z.as_f64()? as f32)),
+        _ => None,
+    }
+}
+
+// Saves the nodes below and including `root`, and the parameters of the animation
+pub fn save(root: &mut SceneNode, animation: &AnimationParams, path: &Path) -> Result<(), String> {
+    let mut nodes = vec![];
+    visit_nodes(root, "", &mut |node, node_path| {
+        nodes.push(json!({
+            "path": node_path,
+            "name": node.name,
+            "position": vec3_json(&node.position),
+            "rotation": vec3_json(&node.rotation),
+            "scale": vec3_json(&node.scale),
+            "reference_point": vec3_json(&node.reference_point),
+        }));
+    });
+    let document = json!({
+        "animation": {
+            "main_rotor_speed": number(animation.main_rotor_speed),
+            "tail_rotor_speed": number(animation.tail_rotor_speed),
+            "spacing": number(animation.spacing),
+            "beacon_flash": number(animation.beacon_flash),
+        },
+        "nodes": nodes,
+    });
+
+    if let Some(directory) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        std::fs::create_dir_all(directory)
+            .map_err(|e| format!("Failed to create {}: {}", directory.display(), e))?;
+    }
+    let text = serde_json::to_string_pretty(&document).map_err(|e| e.to_string())?;
+    std::fs::write(path, text).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+// Applies the scene file at `path`. Nodes that are not in the scene are left out with a warning.
+// A file that does not exist yet has no tweaks, the editor creating it when saving.
+pub fn load(root: &mut SceneNode, animation: &mut AnimationParams, path: &Path) -> Result<(), String> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            println!("No scene file at {} yet, the editor will save there", path.display());
+            return Ok(());
+        }
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+    let document: Value = serde_json::from_str(&text)
+        .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+
+    let params = [
+        ("main_rotor_speed", &mut animation.main_rotor_speed),
+        ("tail_rotor_speed", &mut animation.tail_rotor_speed),
+        ("spacing", &mut animation.spacing),
+        ("beacon_flash", &mut animation.beacon_flash),
+    ];
+    for (name, value) in params {
+        if let Some(v) = document["animation"][name].as_f64() {
+            *value = v as f32;
+        }
+    }
+
+    let saved = document["nodes"].as_array()
+        .ok_or_else(|| format!("{} has no list of nodes", path.display()))?;
+    let mut applied = 0;
+    visit_nodes(root, "", &mut |node, node_path| {
+        if let Some(entry) = saved.iter().find(|n| n["path"] == node_path) {
+            let fields = [
+                ("position", &mut node.position),
+                ("rotation", &mut node.rotation),
+                ("scale", &mut node.scale),
+                ("reference_point", &mut node.reference_point),
+            ];
+            for (name, field) in fields {
+                if let Some(v) = vec3_from_json(&entry[name]) {
+                    *field = v;
+                }
+            }
+            applied += 1;
+        }
+    });
+    if applied < saved.len() {
+        println!("WARNING: {} nodes in {} are not in the scene", saved.len() - applied, path.display());
+    }
+    println!("Applied {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene_graph::Node;
+
+    // A root with two children, the first of them with a child of its own
+    fn tree() -> Node {
+        let mut root = SceneNode::new();
+        let mut body = SceneNode::new();
+        let rotor = SceneNode::new();
+        let door = SceneNode::new();
+        body.name = "Body".to_string();
+        body.add_child(&rotor);
+        root.add_child(&body);
+        root.add_child(&door);
+        root
+    }
+
+    // The transformation of every node by path
+    fn state(root: &mut SceneNode) -> Vec<(String, [glm::Vec3; 4])> {
+        let mut nodes = vec![];
+        visit_nodes(root, "", &mut |node, path| {
+            nodes.push((path.to_string(), [node.position, node.rotation, node.scale, node.reference_point]));
+        });
+        nodes
+    }
+
+    fn params(a: &AnimationParams) -> [f32; 4] {
+        [a.main_rotor_speed, a.tail_rotor_speed, a.spacing, a.beacon_flash]
+    }
+
+    #[test]
+    fn saved_tweaks_load_into_a_new_tree() {
+        let path = std::env::temp_dir().join(format!("gloom-rs-scene-{}.json", std::process::id()));
+        let mut edited = tree();
+        let mut index = 0;
+        visit_nodes(&mut edited, "", &mut |node, _| {
+            index += 1;
+            let k = index as f32;
+            node.position = glm::vec3(k, -k, 0.15);
+            node.rotation = glm::vec3(0.1 * k, 0.0, -0.3);
+            node.scale = glm::vec3(1.0, 2.0, 0.5 + k);
+            node.reference_point = glm::vec3(-k, 0.25, k);
+        });
+        let animation = AnimationParams { spacing: 3.5, beacon_flash: 0.4, ..AnimationParams::default() };
+        save(&mut edited, &animation, &path).unwrap();
+
+        let mut loaded = tree();
+        let mut loaded_animation = AnimationParams::default();
+        let result = load(&mut loaded, &mut loaded_animation, &path);
+        std::fs::remove_file(&path).unwrap();
+        result.unwrap();
+        assert_eq!(state(&mut loaded), state(&mut edited));
+        assert_eq!(params(&loaded_animation), params(&animation));
+    }
+
+    #[test]
+    fn a_missing_file_changes_nothing() {
+        let mut root = tree();
+        let mut animation = AnimationParams::default();
+        let before = state(&mut root);
+        load(&mut root, &mut animation, Path::new("no/such/scene.json")).unwrap();
+        assert_eq!(state(&mut root), before);
+        assert_eq!(params(&animation), params(&AnimationParams::default()));
+    }
+}
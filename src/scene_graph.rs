@@ -2,6 +2,10 @@ extern crate nalgebra_glm as glm;
 
 use std::mem::ManuallyDrop;
 use std::pin::Pin;
+use std::rc::Rc;
+
+use crate::gpu::GpuMesh;
+use crate::light::Light;
 
 // Used to create an unholy abomination upon which you should not cast your gaze. This ended up
 // being a necessity due to wanting to keep the code written by students as "straight forward" as
@@ -16,14 +20,27 @@ use std::pin::Pin;
 // having what I arbitrarily decided to be the required level of "simplicity of use".
 pub type Node = ManuallyDrop<Pin<Box<SceneNode>>>;
 
+// A coarser stand-in for a node's geometry, drawn when the node covers little of the screen
+#[derive(Clone)]
+pub struct Lod {
+    pub mesh            : Rc<GpuMesh>,
+    pub max_screen_size : f32, // Projected diameter in pixels below which I am drawn instead
+}
+
 pub struct SceneNode {
+    pub name            : String,      // What I am called in the editor
     pub position        : glm::Vec3,   // Where I should be in relation to my parent
     pub rotation        : glm::Vec3,   // How I should be rotated, around the X, the Y and the Z axes
     pub scale           : glm::Vec3,   // How I should be scaled
     pub reference_point : glm::Vec3,   // The point I shall rotate and scale about
 
-    pub vao_id      : u32,             // What I should draw
-    pub index_count : i32,             // How much of it there is to draw
+    pub mesh            : Option<Rc<GpuMesh>>, // What I should draw
+
+    pub lods            : Vec<Lod>,    // What I should draw from afar, ordered from finest to coarsest
+    pub bounding_center : glm::Vec3,   // Sphere around my geometry, used to pick a level of detail
+    pub bounding_radius : f32,
+
+    pub lights          : Vec<Light>,  // What I shine, placed relative to me
 
     pub children: Vec<*mut SceneNode>, // Those I command
 }
@@ -32,32 +49,75 @@ impl SceneNode {
 
     pub fn new() -> Node {
         ManuallyDrop::new(Pin::new(Box::new(SceneNode {
+            name            : String::new(),
             position        : glm::zero(),
             rotation        : glm::zero(),
             scale           : glm::vec3(1.0, 1.0, 1.0),
             reference_point : glm::zero(),
-            vao_id          : 0,
-            index_count     : -1,
+            mesh            : None,
+            lods            : vec![],
+            bounding_center : glm::zero(),
+            bounding_radius : 0.0,
+            lights          : vec![],
             children        : vec![],
         })))
     }
 
-    pub fn from_vao(vao_id: u32, index_count: i32) -> Node {
+    pub fn from_mesh(mesh: Rc<GpuMesh>) -> Node {
         ManuallyDrop::new(Pin::new(Box::new(SceneNode {
+            name            : String::new(),
             position        : glm::zero(),
             rotation        : glm::zero(),
             scale           : glm::vec3(1.0, 1.0, 1.0),
             reference_point : glm::zero(),
-            vao_id,
-            index_count,
+            mesh            : Some(mesh),
+            lods            : vec![],
+            bounding_center : glm::zero(),
+            bounding_radius : 0.0,
+            lights          : vec![],
             children: vec![],
         })))
     }
 
+    // Levels must be ordered from finest to coarsest, with decreasing screen sizes
+    pub fn set_lods(&mut self, lods: &[Lod], bounding_sphere: (glm::Vec3, f32)) {
+        self.lods = lods.to_vec();
+        self.bounding_center = bounding_sphere.0;
+        self.bounding_radius = bounding_sphere.1;
+    }
+
+    // The mesh to draw when my bounding sphere covers `screen_size` pixels
+    pub fn select_lod(&self, screen_size: f32) -> Option<&GpuMesh> {
+        let mesh = self.mesh.as_deref()?;
+        Some(self.lods.iter()
+            .take_while(|lod| screen_size < lod.max_screen_size)
+            .last()
+            .map_or(mesh, |lod| &*lod.mesh))
+    }
+
     pub fn add_child(&mut self, child: &SceneNode) {
         self.children.push(child as *const SceneNode as *mut SceneNode)
     }
 
+    #[allow(dead_code)]
+    pub fn remove_child(&mut self, child: &SceneNode) {
+        let child = child as *const SceneNode as *mut SceneNode;
+        self.children.retain(|&c| c != child);
+    }
+
+    // Lets go of my meshes and those of my descendants. The GPU memory is freed once no other node
+    // uses them, since the nodes themselves are never dropped.
+    #[allow(dead_code)]
+    pub fn release_meshes(&mut self) {
+        self.mesh = None;
+        self.lods.clear();
+        for &child in &self.children {
+            if !child.is_null() {
+                unsafe { (*child).release_meshes() }
+            }
+        }
+    }
+
     #[allow(dead_code)]
     pub fn get_child(& mut self, index: usize) -> & mut SceneNode {
         unsafe {
@@ -65,6 +125,48 @@ impl SceneNode {
         }
     }
 
+    // My transformation relative to my parent
+    pub fn local_transform(&self) -> glm::Mat4 {
+        let translate_to_ref = glm::translation(&self.reference_point);
+
+        let rotation_x = glm::rotation(self.rotation.x, &glm::vec3(1.0, 0.0, 0.0));
+        let rotation_y = glm::rotation(self.rotation.y, &glm::vec3(0.0, 1.0, 0.0));
+        let rotation_z = glm::rotation(self.rotation.z, &glm::vec3(0.0, 0.0, 1.0));
+        let rotation = rotation_z * rotation_y * rotation_x;
+
+        let scale = glm::scaling(&self.scale);
+
+        let translate_from_ref = glm::translation(&-self.reference_point);
+
+        let translate_pos = glm::translation(&self.position);
+
+        translate_pos * translate_to_ref * rotation * scale * translate_from_ref
+    }
+
+    // The rotation as a quaternion, matching the Z * Y * X order the angles are applied in
+    #[allow(dead_code)]
+    pub fn rotation_quat(&self) -> glm::Quat {
+        glm::quat_angle_axis(self.rotation.z, &glm::vec3(0.0, 0.0, 1.0))
+            * glm::quat_angle_axis(self.rotation.y, &glm::vec3(0.0, 1.0, 0.0))
+            * glm::quat_angle_axis(self.rotation.x, &glm::vec3(1.0, 0.0, 0.0))
+    }
+
+    // Sets the rotation angles from a quaternion
+    pub fn set_rotation_quat(&mut self, q: &glm::Quat) {
+        let m = glm::quat_to_mat3(q);
+        // m = Rz * Ry * Rx, so m[(2, 0)] = -sin(y)
+        let sin_y = (-m[(2, 0)]).clamp(-1.0, 1.0);
+        self.rotation.y = sin_y.asin();
+        if sin_y.abs() < 0.9999 {
+            self.rotation.x = m[(2, 1)].atan2(m[(2, 2)]);
+            self.rotation.z = m[(1, 0)].atan2(m[(0, 0)]);
+        } else {
+            // Gimbal lock, only the sum or difference of x and z is defined
+            self.rotation.x = 0.0;
+            self.rotation.z = (-m[(0, 1)]).atan2(m[(1, 1)]);
+        }
+    }
+
     #[allow(dead_code)]
     pub fn n_children(&self) -> usize {
         self.children.len()
@@ -76,13 +178,15 @@ impl SceneNode {
 "SceneNode {{
     VAO:       {}
     Indices:   {}
+    LODs:      {}
     Children:  {}
     Position:  [{:.2}, {:.2}, {:.2}]
     Rotation:  [{:.2}, {:.2}, {:.2}]
     Reference: [{:.2}, {:.2}, {:.2}]
 }}",
-            self.vao_id,
-            self.index_count,
+            self.mesh.as_ref().map_or(0, |m| m.vao.id()),
+            self.mesh.as_ref().map_or(0, |m| m.index_count),
+            self.lods.len(),
             self.children.len(),
             self.position.x,
             self.position.y,
@@ -116,3 +220,52 @@ impl IndexMut<usize> for SceneNode {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::{Lod, SceneNode};
+    use crate::headless;
+    use crate::mesh::Mesh;
+    use crate::vertex_layout::VertexLayout;
+
+    #[test]
+    fn select_lod_switches_below_each_screen_size() {
+        let _context = headless::test_context();
+        let triangle = Mesh {
+            vertices: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+            normals: vec![0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0],
+            colors: vec![1.0; 12],
+            indices: vec![0, 1, 2],
+            index_count: 3,
+        };
+        let upload = || Rc::new(unsafe { triangle.upload(&VertexLayout::COMPACT) });
+        let lods: Vec<Lod> = [250.0, 100.0, 40.0].iter()
+            .map(|&max_screen_size| Lod { mesh: upload(), max_screen_size })
+            .collect();
+
+        let mut node = SceneNode::from_mesh(upload());
+        node.set_lods(&lods, (glm::zero(), 1.0));
+        let full = node.mesh.clone().unwrap();
+        let expected = [
+            (1000.0, &full),
+            (250.0, &full),
+            (249.0, &lods[0].mesh),
+            (100.0, &lods[0].mesh),
+            (99.0, &lods[1].mesh),
+            (40.0, &lods[1].mesh),
+            (39.0, &lods[2].mesh),
+            (0.0, &lods[2].mesh),
+        ];
+        for (screen_size, mesh) in expected {
+            let selected = node.select_lod(screen_size).unwrap();
+            assert!(std::ptr::eq(selected, &**mesh), "Wrong level of detail at {} pixels", screen_size);
+        }
+
+        // Nodes without geometry draw nothing at any size
+        let mut empty = SceneNode::new();
+        empty.set_lods(&lods, (glm::zero(), 1.0));
+        assert!(empty.select_lod(10.0).is_none());
+    }
+}
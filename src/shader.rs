@@ -1,49 +1,279 @@
-use gl;
 use std::{
     ptr,
     str,
+    cell::RefCell,
+    collections::{HashMap, HashSet},
     ffi::CString,
-    path::Path,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
 };
 
+use crate::gpu::Program;
+use crate::embedded_shaders;
+use crate::preprocess::preprocess;
+use crate::program_cache;
+
+// How often the source files of a shader are checked for modifications
+const RELOAD_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
 pub struct Shader {
-    pub program_id: u32,
+    pub program: Program,
+    pub uniforms: HashMap<String, Variable>,   // Active uniforms outside of blocks
+    pub attributes: HashMap<String, Variable>, // Active vertex shader inputs
+    pub blocks: HashMap<String, Block>,        // Active uniform blocks
+    sources: Vec<(PathBuf, ShaderType)>,
+    defines: Vec<(String, String)>,
+    watched: Vec<PathBuf>,              // Source files and the files they include
+    modified: Vec<Option<SystemTime>>,  // Last seen modification time of each watched file
+    last_check: Instant,
+    cache_key: u64,                     // Of the program binary in program_cache
+    warned: RefCell<HashSet<String>>,  // Names already warned about, so each is reported once
 }
 
-pub struct ShaderBuilder {
-    program_id: u32,
-    shaders: Vec::<u32>,
+// An active uniform or attribute, as reported by the driver after linking
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug)]
+pub struct Variable {
+    pub location : i32,
+    pub gl_type  : gl::types::GLenum, // e.g. gl::FLOAT_MAT4
+    pub size     : i32,               // Number of elements for arrays, otherwise 1
 }
 
 #[allow(dead_code)]
+#[derive(Clone, Copy, Debug)]
+pub struct Block {
+    pub index     : u32,
+    pub binding   : u32, // Buffer binding point, set with `layout(binding = n)`
+    pub data_size : i32, // In bytes
+}
+
+pub struct ShaderBuilder {
+    sources: Vec<(PathBuf, ShaderType)>,
+    defines: Vec<(String, String)>,
+}
+
+#[derive(Clone, Copy)]
 pub enum ShaderType {
     Vertex,
     Fragment,
     TessellationControl,
     TessellationEvaluation,
     Geometry,
+    Compute,
 }
 
 impl Shader {
-    // Make sure the shader is active before calling this
-    pub unsafe fn get_uniform_location(&self, name: &str) -> i32 {
-        let name_cstr = CString::new(name).expect("CString::new failed");
-        gl::GetUniformLocation(self.program_id, name_cstr.as_ptr())
+    // -1 if the program has no active uniform of that name
+    #[allow(dead_code)]
+    pub fn get_uniform_location(&self, name: &str) -> i32 {
+        self.uniforms.get(name).map_or(-1, |u| u.location)
+    }
+
+    // The setters write to the active program, make sure the shader is active before calling them.
+    // Unknown names and mismatching types are reported once and otherwise ignored, as uniforms
+    // the compiler optimized away are not active.
+    pub unsafe fn set_mat4(&self, name: &str, value: &glm::Mat4) {
+        if let Some(location) = self.checked_location(name, gl::FLOAT_MAT4) {
+            gl::UniformMatrix4fv(location, 1, gl::FALSE, value.as_ptr());
+        }
+    }
+
+    pub unsafe fn set_vec2(&self, name: &str, value: &glm::Vec2) {
+        if let Some(location) = self.checked_location(name, gl::FLOAT_VEC2) {
+            gl::Uniform2fv(location, 1, value.as_ptr());
+        }
+    }
+
+    #[allow(dead_code)]
+    pub unsafe fn set_vec3(&self, name: &str, value: &glm::Vec3) {
+        if let Some(location) = self.checked_location(name, gl::FLOAT_VEC3) {
+            gl::Uniform3fv(location, 1, value.as_ptr());
+        }
+    }
+
+    pub unsafe fn set_i32(&self, name: &str, value: i32) {
+        if let Some(location) = self.checked_location(name, gl::INT) {
+            gl::Uniform1i(location, value);
+        }
+    }
+
+    pub unsafe fn set_f32(&self, name: &str, value: f32) {
+        if let Some(location) = self.checked_location(name, gl::FLOAT) {
+            gl::Uniform1f(location, value);
+        }
+    }
+
+    pub unsafe fn set_bool(&self, name: &str, value: bool) {
+        if let Some(location) = self.checked_location(name, gl::BOOL) {
+            gl::Uniform1i(location, value as i32);
+        }
+    }
+
+    fn checked_location(&self, name: &str, gl_type: gl::types::GLenum) -> Option<i32> {
+        let uniform = self.uniforms.get(name);
+        let problem = match uniform {
+            None => format!("no active uniform named {}", name),
+            Some(u) if u.gl_type != gl_type => format!("uniform {} is a {}, not a {}",
+                name, type_name(u.gl_type), type_name(gl_type)),
+            Some(u) => return Some(u.location),
+        };
+        self.warn_once(name, &problem);
+        None
+    }
+
+    fn warn_once(&self, name: &str, problem: &str) {
+        if self.warned.borrow_mut().insert(name.to_string()) {
+            println!("WARNING: Shader {}: {}", self.source_names().join(", "), problem);
+        }
+    }
+
+    // Queries the active uniforms, attributes and uniform blocks of the program
+    unsafe fn reflect(&mut self) {
+        let id = self.program.id();
+        self.uniforms = active_variables(id, gl::ACTIVE_UNIFORMS, gl::ACTIVE_UNIFORM_MAX_LENGTH, |i, len, size, ty, name| {
+            gl::GetActiveUniform(id, i, len, ptr::null_mut(), size, ty, name)
+        }, |name| gl::GetUniformLocation(id, name));
+        // Uniforms in blocks have no location
+        self.uniforms.retain(|_, u| u.location >= 0);
+        self.attributes = active_variables(id, gl::ACTIVE_ATTRIBUTES, gl::ACTIVE_ATTRIBUTE_MAX_LENGTH, |i, len, size, ty, name| {
+            gl::GetActiveAttrib(id, i, len, ptr::null_mut(), size, ty, name)
+        }, |name| gl::GetAttribLocation(id, name));
+
+        self.blocks = active_blocks(id, gl::UNIFORM_BLOCK);
+        self.warned.borrow_mut().clear();
+    }
+
+    fn source_names(&self) -> Vec<String> {
+        self.sources.iter().map(|(path, _)| path.display().to_string()).collect()
     }
 
     pub unsafe fn activate(&self) {
-        gl::UseProgram(self.program_id);
+        gl::UseProgram(self.program.id());
+    }
+
+    // Rebuilds the program if any of its source files changed since it was built. If the new
+    // version fails to compile or link, the error is printed and the previous program is kept.
+    // Returns true when the program was replaced, it then has to be activated again.
+    pub unsafe fn reload_if_changed(&mut self) -> bool {
+        if self.last_check.elapsed() < RELOAD_CHECK_INTERVAL {
+            return false;
+        }
+        self.last_check = Instant::now();
+
+        let modified = modification_times(&self.watched);
+        if modified == self.modified {
+            return false;
+        }
+
+        // Remember the new times even if the build fails, so it is only retried after the next
+        // edit. The includes may have changed, so the files to watch are updated either way.
+        let mut watched = vec![];
+        let result = build_program(&self.sources, &self.defines, &mut watched);
+        self.watched = watched;
+        self.modified = modification_times(&self.watched);
+
+        match result {
+            Ok((program, cache_key)) => {
+                // The previous binary is left over in the cache otherwise, as it is only looked
+                // up with the sources it was built from
+                if cache_key != self.cache_key {
+                    program_cache::remove(self.cache_key);
+                }
+                self.program = program;
+                self.cache_key = cache_key;
+                self.reflect();
+                println!("Reloaded shader {}", self.source_names().join(", "));
+                true
+            }
+            Err(log) => {
+                println!("{}\nKeeping the previous version of the shader.", log);
+                false
+            }
+        }
+    }
+}
+
+// Lists the active uniforms or attributes through `get_active`. Arrays are reported by the
+// driver as `name[0]`, they are also stored under the plain name.
+unsafe fn active_variables(
+    program: u32,
+    count_query: gl::types::GLenum,
+    max_length_query: gl::types::GLenum,
+    get_active: impl Fn(u32, i32, *mut i32, *mut gl::types::GLenum, *mut gl::types::GLchar),
+    get_location: impl Fn(*const gl::types::GLchar) -> i32,
+) -> HashMap<String, Variable> {
+    let mut count = 0;
+    gl::GetProgramiv(program, count_query, &mut count);
+    let mut max_length = 0;
+    gl::GetProgramiv(program, max_length_query, &mut max_length);
+
+    let mut variables = HashMap::new();
+    for i in 0..count as u32 {
+        let mut name = vec![0u8; max_length.max(1) as usize];
+        let mut size = 0;
+        let mut gl_type = 0;
+        get_active(i, name.len() as i32, &mut size, &mut gl_type, name.as_mut_ptr() as *mut gl::types::GLchar);
+        let location = get_location(name.as_ptr() as *const gl::types::GLchar);
+        let name = log_to_string(&name);
+        let variable = Variable { location, gl_type, size };
+        if let Some(base) = name.strip_suffix("[0]") {
+            variables.insert(base.to_string(), variable);
+        }
+        variables.insert(name, variable);
+    }
+    variables
+}
+
+// Lists the active blocks of `interface`, gl::UNIFORM_BLOCK or gl::SHADER_STORAGE_BLOCK
+unsafe fn active_blocks(program: u32, interface: gl::types::GLenum) -> HashMap<String, Block> {
+    let mut count = 0;
+    gl::GetProgramInterfaceiv(program, interface, gl::ACTIVE_RESOURCES, &mut count);
+    let mut max_length = 0;
+    gl::GetProgramInterfaceiv(program, interface, gl::MAX_NAME_LENGTH, &mut max_length);
+
+    let mut blocks = HashMap::new();
+    for index in 0..count as u32 {
+        let mut name = vec![0u8; max_length.max(1) as usize];
+        gl::GetProgramResourceName(program, interface, index, name.len() as i32, ptr::null_mut(), name.as_mut_ptr() as *mut gl::types::GLchar);
+        let properties = [gl::BUFFER_BINDING, gl::BUFFER_DATA_SIZE];
+        let mut values = [0; 2];
+        gl::GetProgramResourceiv(program, interface, index, 2, properties.as_ptr(), 2, ptr::null_mut(), values.as_mut_ptr());
+        blocks.insert(log_to_string(&name), Block { index, binding: values[0] as u32, data_size: values[1] });
+    }
+    blocks
+}
+
+fn type_name(gl_type: gl::types::GLenum) -> &'static str {
+    match gl_type {
+        gl::FLOAT => "float",
+        gl::FLOAT_VEC2 => "vec2",
+        gl::FLOAT_VEC3 => "vec3",
+        gl::FLOAT_VEC4 => "vec4",
+        gl::FLOAT_MAT3 => "mat3",
+        gl::FLOAT_MAT4 => "mat4",
+        gl::INT => "int",
+        gl::UNSIGNED_INT => "uint",
+        gl::BOOL => "bool",
+        gl::SAMPLER_2D => "sampler2D",
+        _ => "other type",
     }
 }
 
-impl Into<gl::types::GLenum> for ShaderType {
-    fn into(self) -> gl::types::GLenum {
-        match self {
+fn modification_times(paths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    paths.iter()
+        .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+impl From<ShaderType> for gl::types::GLenum {
+    fn from(shader_type: ShaderType) -> gl::types::GLenum {
+        match shader_type {
             ShaderType::Vertex                  => { gl::VERTEX_SHADER          },
             ShaderType::Fragment                => { gl::FRAGMENT_SHADER        },
             ShaderType::TessellationControl     => { gl::TESS_CONTROL_SHADER    },
             ShaderType::TessellationEvaluation  => { gl::TESS_EVALUATION_SHADER } ,
             ShaderType::Geometry                => { gl::GEOMETRY_SHADER        },
+            ShaderType::Compute                 => { gl::COMPUTE_SHADER         },
         }
     }
 }
@@ -56,6 +286,7 @@ impl ShaderType {
             "tcs"  => { Ok(ShaderType::TessellationControl) },
             "tes"  => { Ok(ShaderType::TessellationEvaluation) },
             "geom" => { Ok(ShaderType::Geometry) },
+            "comp" => { Ok(ShaderType::Compute) },
             e => { Err(e.to_string()) },
         }
     }
@@ -64,91 +295,193 @@ impl ShaderType {
 impl ShaderBuilder {
     pub unsafe fn new() -> ShaderBuilder {
         ShaderBuilder {
-            program_id: gl::CreateProgram(),
-            shaders: vec![],
+            sources: vec![],
+            defines: vec![],
         }
     }
 
-    pub unsafe fn attach_file(self, shader_path: &str) -> ShaderBuilder {
+    // Adds `#define name value` to every stage, after the version directive
+    pub fn define(mut self, name: &str, value: &str) -> ShaderBuilder {
+        self.defines.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    // Attaches the shader registered as `name` in embedded_shaders, or its override on disk
+    pub unsafe fn attach(self, name: &str) -> ShaderBuilder {
+        let path = embedded_shaders::override_path(name);
+        self.attach_file(&path.to_string_lossy())
+    }
+
+    // Sources are only read and compiled by `link`, which reports any problems
+    pub unsafe fn attach_file(mut self, shader_path: &str) -> ShaderBuilder {
         let path = Path::new(shader_path);
         if let Some(extension) = path.extension() {
             let shader_type = ShaderType::from_ext(extension)
                 .expect("Failed to parse file extension.");
-            let shader_src = std::fs::read_to_string(path)
-                .expect(&format!("Failed to read shader source. {}", shader_path));
-            self.compile_shader(&shader_src, shader_type)
+            self.sources.push((path.to_path_buf(), shader_type));
+            self
         } else {
             panic!("Failed to read extension of file with path: {}", shader_path);
         }
     }
 
-    pub unsafe fn compile_shader(mut self, shader_src: &str, shader_type: ShaderType) -> ShaderBuilder {
-        let shader = gl::CreateShader(shader_type.into());
-        let c_str_shader = CString::new(shader_src.as_bytes()).unwrap();
+    // Compiles and links the attached sources. The error holds the compiler or linker log, with
+    // the locations in it pointing to the source files.
+    #[must_use = "The shader program is useless if not stored in a variable."]
+    pub unsafe fn link(self) -> Result<Shader, String> {
+        let mut watched = vec![];
+        let (program, cache_key) = build_program(&self.sources, &self.defines, &mut watched)?;
+        let mut shader = Shader {
+            program,
+            uniforms: HashMap::new(),
+            attributes: HashMap::new(),
+            blocks: HashMap::new(),
+            modified: modification_times(&watched),
+            watched,
+            sources: self.sources,
+            defines: self.defines,
+            last_check: Instant::now(),
+            cache_key,
+            warned: RefCell::new(HashSet::new()),
+        };
+        shader.reflect();
+        Ok(shader)
+    }
+}
+
+// Returns the program and its key in program_cache. The files read are pushed to `watched`, also
+// when the build fails.
+unsafe fn build_program(
+    sources: &[(PathBuf, ShaderType)],
+    defines: &[(String, String)],
+    watched: &mut Vec<PathBuf>,
+) -> Result<(Program, u64), String> {
+    watched.extend(sources.iter().map(|(path, _)| path.clone()));
+    let mut shaders = vec![];
+    let result = compile_and_link(sources, defines, watched, &mut shaders);
+    for shader in shaders {
+        gl::DeleteShader(shader);
+    }
+    result
+}
+
+// Compiled shader objects are pushed to `shaders`, for the caller to delete whether this succeeds or not
+unsafe fn compile_and_link(
+    sources: &[(PathBuf, ShaderType)],
+    defines: &[(String, String)],
+    watched: &mut Vec<PathBuf>,
+    shaders: &mut Vec<u32>,
+) -> Result<(Program, u64), String> {
+    let mut stages = vec![];
+    for (path, shader_type) in sources {
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let text = preprocess(&embedded_shaders::read(path)?, &path.display().to_string(), dir, defines, watched)?;
+        stages.push((path, *shader_type, text));
+    }
+
+    let key = program_cache::key(&stages.iter()
+        .map(|(_, shader_type, text)| ((*shader_type).into(), text.source.as_str()))
+        .collect::<Vec<_>>());
+    if let Some(program) = program_cache::load(key) {
+        return Ok((program, key));
+    }
+
+    for (path, shader_type, text) in &stages {
+        let shader = gl::CreateShader((*shader_type).into());
+        shaders.push(shader);
+
+        let c_str_shader = CString::new(text.source.as_bytes())
+            .map_err(|_| format!("{} contains a null byte", path.display()))?;
         gl::ShaderSource(shader, 1, &c_str_shader.as_ptr(), ptr::null());
         gl::CompileShader(shader);
+        check_shader_errors(shader)
+            .map_err(|log| format!("Failed to compile {}:\n{}", path.display(), locate_log(&log, &text.files)))?;
+    }
 
-        if !self.check_shader_errors(shader) {
-            panic!("Shader failed to compile.");
-        }
-
-        self.shaders.push(shader);
+    let program = Program::new();
+    for &shader in shaders.iter() {
+        gl::AttachShader(program.id(), shader);
+    }
+    program_cache::prepare(&program);
+    gl::LinkProgram(program.id());
+    check_linker_errors(&program).map_err(|log| {
+        let names: Vec<String> = sources.iter().map(|(path, _)| path.display().to_string()).collect();
+        format!("Failed to link {}:\n{}", names.join(", "), log)
+    })?;
+    program_cache::store(key, &program);
+    Ok((program, key))
+}
 
-        self
+unsafe fn check_shader_errors(shader_id: u32) -> Result<(), String> {
+    let mut success = i32::from(gl::FALSE);
+    gl::GetShaderiv(shader_id, gl::COMPILE_STATUS, &mut success);
+    if success != i32::from(gl::TRUE) {
+        let mut length = 0;
+        gl::GetShaderiv(shader_id, gl::INFO_LOG_LENGTH, &mut length);
+        let mut info_log = vec![0u8; length.max(1) as usize];
+        gl::GetShaderInfoLog(
+            shader_id,
+            info_log.len() as i32,
+            ptr::null_mut(),
+            info_log.as_mut_ptr() as *mut gl::types::GLchar,
+        );
+        return Err(log_to_string(&info_log));
     }
+    Ok(())
+}
 
-    unsafe fn check_shader_errors(&self, shader_id: u32) -> bool {
-        let mut success = i32::from(gl::FALSE);
-        let mut info_log = Vec::with_capacity(512);
-        info_log.set_len(512 - 1);
-        gl::GetShaderiv(shader_id, gl::COMPILE_STATUS, &mut success);
-        if success != i32::from(gl::TRUE) {
-            gl::GetShaderInfoLog(
-                shader_id,
-                512,
-                ptr::null_mut(),
-                info_log.as_mut_ptr() as *mut gl::types::GLchar,
-            );
-            println!("ERROR::Shader Compilation Failed!\n{}", String::from_utf8_lossy(&info_log));
-            return false;
-        }
-        true
-    }
-
-    unsafe fn check_linker_errors(&self) -> bool {
-        let mut success = i32::from(gl::FALSE);
-        let mut info_log = Vec::with_capacity(512);
-        info_log.set_len(512 - 1);
-        gl::GetProgramiv(self.program_id, gl::LINK_STATUS, &mut success);
-        if success != i32::from(gl::TRUE) {
-            gl::GetProgramInfoLog(
-                self.program_id,
-                512,
-                ptr::null_mut(),
-                info_log.as_mut_ptr() as *mut gl::types::GLchar,
-            );
-            println!("ERROR::SHADER::PROGRAM::COMPILATION_FAILED\n{}", String::from_utf8_lossy(&info_log));
-            return false;
-        }
-        true
+unsafe fn check_linker_errors(program: &Program) -> Result<(), String> {
+    let mut success = i32::from(gl::FALSE);
+    gl::GetProgramiv(program.id(), gl::LINK_STATUS, &mut success);
+    if success != i32::from(gl::TRUE) {
+        let mut length = 0;
+        gl::GetProgramiv(program.id(), gl::INFO_LOG_LENGTH, &mut length);
+        let mut info_log = vec![0u8; length.max(1) as usize];
+        gl::GetProgramInfoLog(
+            program.id(),
+            info_log.len() as i32,
+            ptr::null_mut(),
+            info_log.as_mut_ptr() as *mut gl::types::GLchar,
+        );
+        return Err(log_to_string(&info_log));
     }
+    Ok(())
+}
 
-    #[must_use = "The shader program is useless if not stored in a variable."]
-    pub unsafe fn link(self) -> Shader {
-        for &shader in &self.shaders {
-            gl::AttachShader(self.program_id, shader);
-        }
-        gl::LinkProgram(self.program_id);
+// The log is null terminated
+fn log_to_string(info_log: &[u8]) -> String {
+    let end = info_log.iter().position(|&b| b == 0).unwrap_or(info_log.len());
+    String::from_utf8_lossy(&info_log[..end]).trim_end().to_string()
+}
 
-        // todo:: use this to make safer abstraction
-        self.check_linker_errors();
+// Rewrites the locations at the start of each line of a compiler log to `file:line`, `files` being
+// the names by source string number. Drivers refer to the source string by number, as in
+// `0:12(5): error` (Mesa), `0(12) : error` (NVIDIA) and `ERROR: 0:12: ` (AMD, Intel).
+fn locate_log(log: &str, files: &[String]) -> String {
+    log.lines()
+        .map(|line| locate_line(line, files).unwrap_or_else(|| line.to_string()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
 
-        for &shader in &self.shaders {
-            gl::DeleteShader(shader);
-        }
+fn locate_line(line: &str, files: &[String]) -> Option<String> {
+    let (prefix, rest) = ["ERROR: ", "WARNING: "].iter()
+        .find_map(|p| line.strip_prefix(p).map(|rest| (*p, rest)))
+        .unwrap_or(("", line));
 
-        Shader {
-            program_id: self.program_id
-        }
+    let digits = |s: &str| s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let index_end = digits(rest);
+    let name = files.get(rest[..index_end].parse::<usize>().ok()?)?;
+    let (line_number, rest) = if let Some(after) = rest[index_end..].strip_prefix(':') {
+        let end = digits(after);
+        (&after[..end], &after[end..])
+    } else {
+        let after = rest[index_end..].strip_prefix('(')?;
+        let end = digits(after);
+        (&after[..end], after[end..].strip_prefix(')')?)
+    };
+    if line_number.is_empty() {
+        return None;
     }
+    Some(format!("{}{}:{}{}", prefix, name, line_number, rest))
 }
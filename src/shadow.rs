@@ -0,0 +1,93 @@
+// Shadow mapping for the directional light
+//
+// The scene is first drawn from the light into a depth texture, covering a fixed region of the
+// world. The main shader then compares the depth of each fragment seen from the light with the
+// texture, averaging over neighbouring texels (percentage closer filtering) to soften the edges.
+
+use crate::gpu::{Framebuffer, Texture};
+
+// Texture unit the shadow map is bound to, `layout(binding = 1)` in simple.frag
+pub const SHADOW_MAP_UNIT: u32 = 1;
+
+pub struct ShadowMap {
+    pub resolution : i32,       // Width and height of the depth texture in texels
+    pub bias       : f32,       // Depth offset against shadow acne, in light space depth
+    pub pcf_radius : i32,       // Texels sampled in each direction around the fragment, 0 for hard shadows
+    pub center     : glm::Vec3, // The region of the world that gets shadows, a sphere
+    pub radius     : f32,
+    framebuffer    : Framebuffer,
+    depth          : Texture,
+}
+
+impl ShadowMap {
+    pub unsafe fn new(resolution: i32, center: glm::Vec3, radius: f32) -> Self {
+        let depth = Texture::new(gl::TEXTURE_2D);
+        depth.bind(SHADOW_MAP_UNIT);
+        gl::TexImage2D(
+            gl::TEXTURE_2D,
+            0,
+            gl::DEPTH_COMPONENT32F as i32,
+            resolution,
+            resolution,
+            0,
+            gl::DEPTH_COMPONENT,
+            gl::FLOAT,
+            std::ptr::null(),
+        );
+        // Linear filtering on a comparison sampler blends the results of the four nearest texels
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_COMPARE_MODE, gl::COMPARE_REF_TO_TEXTURE as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_COMPARE_FUNC, gl::LEQUAL as i32);
+        // Everything outside of the region is lit
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_BORDER as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_BORDER as i32);
+        gl::TexParameterfv(gl::TEXTURE_2D, gl::TEXTURE_BORDER_COLOR, [1.0f32; 4].as_ptr());
+
+        let framebuffer = Framebuffer::new();
+        framebuffer.bind();
+        gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::TEXTURE_2D, depth.id(), 0);
+        gl::DrawBuffer(gl::NONE);
+        gl::ReadBuffer(gl::NONE);
+        let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+        assert_eq!(status, gl::FRAMEBUFFER_COMPLETE, "Shadow map framebuffer is incomplete");
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+
+        ShadowMap {
+            resolution,
+            bias: 0.002,
+            pcf_radius: 1,
+            center,
+            radius,
+            framebuffer,
+            depth,
+        }
+    }
+
+    // Projection from world space into the light, for a light travelling along `direction`. It
+    // is orthographic, as the light is infinitely far away.
+    pub fn light_matrix(&self, direction: &glm::Vec3) -> glm::Mat4 {
+        let direction = glm::normalize(direction);
+        let up = if direction.y.abs() > 0.99 { glm::vec3(0.0, 0.0, 1.0) } else { glm::vec3(0.0, 1.0, 0.0) };
+        let eye = self.center - direction * self.radius * 2.0;
+        let view = glm::look_at(&eye, &self.center, &up);
+        let r = self.radius;
+        let projection = glm::ortho(-r, r, -r, r, self.radius, self.radius * 3.0);
+        projection * view
+    }
+
+    // Binds the framebuffer and clears it. Draw the shadow casters, then call `end`.
+    pub unsafe fn begin(&self) {
+        self.framebuffer.bind();
+        gl::Viewport(0, 0, self.resolution, self.resolution);
+        gl::Clear(gl::DEPTH_BUFFER_BIT);
+    }
+
+    // Goes back to drawing to `framebuffer`, 0 for the window, and binds the shadow map for the
+    // main pass
+    pub unsafe fn end(&self, framebuffer: u32, width: i32, height: i32) {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+        gl::Viewport(0, 0, width, height);
+        self.depth.bind(SHADOW_MAP_UNIT);
+    }
+}
@@ -0,0 +1,487 @@
+// Edge-collapse mesh simplification driven by quadric error metrics (Garland & Heckbert, 1997).
+//
+// Every vertex accumulates the planes of the triangles around it as a 4x4 quadric. Collapsing an
+// edge merges the two endpoint quadrics, and the error of placing the merged vertex at v is
+// v^T Q v. Edges are collapsed cheapest-first until the triangle budget is met.
+//
+// Vertices sharing a position are welded before simplifying so that seams in the source mesh do
+// not tear open, which means hard normals are lost. Normals of the result are recomputed as
+// area-weighted averages, which is fine for the distances the coarser levels are drawn at.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+
+use crate::mesh::Mesh;
+
+// Extra weight given to the planes that keep open boundaries (e.g. the edge of the terrain) in place
+const BOUNDARY_WEIGHT: f64 = 1000.0;
+
+// Collapses that turn a triangle further than this (cosine of the angle) are rejected
+const MIN_NORMAL_ALIGNMENT: f64 = 0.2;
+
+// Symmetric 4x4 matrix stored as its upper triangle
+#[derive(Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn from_plane(a: f64, b: f64, c: f64, d: f64, weight: f64) -> Quadric {
+        Quadric([
+            a * a, a * b, a * c, a * d,
+                   b * b, b * c, b * d,
+                          c * c, c * d,
+                                 d * d,
+        ].map(|q| q * weight))
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for (q, o) in self.0.iter_mut().zip(other.0.iter()) {
+            *q += o;
+        }
+    }
+
+    fn error(&self, v: &[f64; 3]) -> f64 {
+        let q = &self.0;
+        let [x, y, z] = *v;
+        q[0] * x * x + 2.0 * q[1] * x * y + 2.0 * q[2] * x * z + 2.0 * q[3] * x
+                     +       q[4] * y * y + 2.0 * q[5] * y * z + 2.0 * q[6] * y
+                                          +       q[7] * z * z + 2.0 * q[8] * z
+                                                               +       q[9]
+    }
+
+    // The point minimizing the error, if the quadric is well conditioned
+    fn optimum(&self) -> Option<[f64; 3]> {
+        let q = &self.0;
+        let m = [
+            [q[0], q[1], q[2]],
+            [q[1], q[4], q[5]],
+            [q[2], q[5], q[7]],
+        ];
+        let rhs = [-q[3], -q[6], -q[8]];
+        let det = determinant(&m);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        // Cramer's rule
+        let mut result = [0.0; 3];
+        for (i, r) in result.iter_mut().enumerate() {
+            let mut mi = m;
+            for row in 0..3 {
+                mi[row][i] = rhs[row];
+            }
+            *r = determinant(&mi) / det;
+        }
+        Some(result)
+    }
+}
+
+fn determinant(m: &[[f64; 3]; 3]) -> f64 {
+    m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+        - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+}
+
+fn sub(a: &[f64; 3], b: &[f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn cross(a: &[f64; 3], b: &[f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn dot(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn length(a: &[f64; 3]) -> f64 {
+    dot(a, a).sqrt()
+}
+
+// Unnormalized face normal, its length is twice the triangle area
+fn face_normal(p: &[[f64; 3]], tri: &[usize; 3]) -> [f64; 3] {
+    cross(&sub(&p[tri[1]], &p[tri[0]]), &sub(&p[tri[2]], &p[tri[0]]))
+}
+
+// A candidate collapse of the edge (a, b) into a single vertex at `position`
+struct Collapse {
+    cost     : f64,
+    a        : usize,
+    b        : usize,
+    position : [f64; 3],
+    stamp    : (u32, u32), // Versions of a and b when this was computed
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for Collapse {}
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Collapse {
+    // Reversed, so that the BinaryHeap pops the cheapest collapse first. Ties go to the lowest
+    // edge, so that the result does not depend on the order the edges were queued in.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+            .then_with(|| (other.a, other.b).cmp(&(self.a, self.b)))
+    }
+}
+
+struct Simplifier {
+    positions : Vec<[f64; 3]>,
+    quadrics  : Vec<Quadric>,
+    versions  : Vec<u32>,
+    alive     : Vec<bool>,
+    triangles : Vec<[usize; 3]>,
+    tri_alive : Vec<bool>,
+    adjacent  : Vec<Vec<usize>>, // Triangles around each vertex
+    heap      : BinaryHeap<Collapse>,
+}
+
+impl Simplifier {
+    fn plan(&self, a: usize, b: usize) -> Collapse {
+        let mut q = self.quadrics[a];
+        q.add(&self.quadrics[b]);
+        let pa = self.positions[a];
+        let pb = self.positions[b];
+        let mid = [(pa[0] + pb[0]) * 0.5, (pa[1] + pb[1]) * 0.5, (pa[2] + pb[2]) * 0.5];
+        let position = q.optimum()
+            // Reject solutions far away from the edge, they come from nearly singular quadrics
+            .filter(|p| length(&sub(p, &mid)) <= 2.0 * length(&sub(&pa, &pb)) + 1e-9)
+            .unwrap_or_else(|| {
+                [pa, pb, mid].iter().copied()
+                    .min_by(|x, y| q.error(x).total_cmp(&q.error(y)))
+                    .unwrap()
+            });
+        Collapse {
+            cost: q.error(&position).max(0.0),
+            a,
+            b,
+            position,
+            stamp: (self.versions[a], self.versions[b]),
+        }
+    }
+
+    fn push_edges_of(&mut self, v: usize) {
+        let mut neighbours: Vec<usize> = self.adjacent[v].iter()
+            .filter(|&&t| self.tri_alive[t])
+            .flat_map(|&t| self.triangles[t])
+            .filter(|&n| n != v)
+            .collect();
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        for n in neighbours {
+            let collapse = self.plan(v, n);
+            self.heap.push(collapse);
+        }
+    }
+
+    // Would moving a and b to `position` fold any of the surrounding triangles over?
+    fn flips(&self, a: usize, b: usize, position: &[f64; 3]) -> bool {
+        for &v in &[a, b] {
+            for &t in &self.adjacent[v] {
+                let tri = self.triangles[t];
+                if !self.tri_alive[t] || (tri.contains(&a) && tri.contains(&b)) {
+                    continue; // Gone already, or this one disappears
+                }
+                let before = face_normal(&self.positions, &tri);
+                let mut moved = [self.positions[tri[0]], self.positions[tri[1]], self.positions[tri[2]]];
+                for (corner, &i) in tri.iter().enumerate() {
+                    if i == v {
+                        moved[corner] = *position;
+                    }
+                }
+                let after = face_normal(&moved, &[0, 1, 2]);
+                let (lb, la) = (length(&before), length(&after));
+                if la < 1e-12 || (lb > 1e-12 && dot(&before, &after) < MIN_NORMAL_ALIGNMENT * lb * la) {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    // Merges b into a, returns how many triangles were removed
+    fn collapse(&mut self, a: usize, b: usize, position: [f64; 3]) -> usize {
+        let mut removed = 0;
+        self.positions[a] = position;
+        let qb = self.quadrics[b];
+        self.quadrics[a].add(&qb);
+        self.alive[b] = false;
+
+        let moved = std::mem::take(&mut self.adjacent[b]);
+        for t in moved {
+            if !self.tri_alive[t] {
+                continue;
+            }
+            let tri = &mut self.triangles[t];
+            if tri.contains(&a) {
+                self.tri_alive[t] = false;
+                removed += 1;
+            } else {
+                for i in tri.iter_mut() {
+                    if *i == b {
+                        *i = a;
+                    }
+                }
+                self.adjacent[a].push(t);
+            }
+        }
+
+        let tri_alive = &self.tri_alive;
+        self.adjacent[a].retain(|&t| tri_alive[t]);
+        self.adjacent[a].sort_unstable();
+        self.adjacent[a].dedup();
+        self.versions[a] += 1;
+        self.versions[b] += 1;
+        self.push_edges_of(a);
+        removed
+    }
+}
+
+// Reduces `mesh` to at most `target_triangles` triangles, or as close as it can get without
+// folding the surface over itself.
+pub fn simplify(mesh: &Mesh, target_triangles: usize) -> Mesh {
+    // Weld vertices sharing a position
+    let mut welded: HashMap<[u32; 3], usize> = HashMap::new();
+    let mut remap = Vec::with_capacity(mesh.vertices.len() / 3);
+    let mut positions = vec![];
+    let mut colors = vec![];
+    for (i, p) in mesh.vertices.chunks_exact(3).enumerate() {
+        let key = [p[0].to_bits(), p[1].to_bits(), p[2].to_bits()];
+        let index = *welded.entry(key).or_insert_with(|| {
+            positions.push([p[0] as f64, p[1] as f64, p[2] as f64]);
+            colors.push(mesh.colors.get(i * 4..i * 4 + 4).map_or([1.0; 4], |c| [c[0], c[1], c[2], c[3]]));
+            positions.len() - 1
+        });
+        remap.push(index);
+    }
+
+    let triangles: Vec<[usize; 3]> = mesh.indices.chunks_exact(3)
+        .map(|t| [remap[t[0] as usize], remap[t[1] as usize], remap[t[2] as usize]])
+        .filter(|t| t[0] != t[1] && t[1] != t[2] && t[2] != t[0])
+        .collect();
+
+    let mut quadrics = vec![Quadric::default(); positions.len()];
+    let mut adjacent = vec![vec![]; positions.len()];
+    // Ordered, so that the quadrics are summed in the same order every time
+    let mut edge_use: BTreeMap<(usize, usize), (usize, usize)> = BTreeMap::new();
+    for (t, tri) in triangles.iter().enumerate() {
+        let n = face_normal(&positions, tri);
+        let area2 = length(&n);
+        if area2 > 1e-12 {
+            let n = [n[0] / area2, n[1] / area2, n[2] / area2];
+            let d = -dot(&n, &positions[tri[0]]);
+            let q = Quadric::from_plane(n[0], n[1], n[2], d, area2 * 0.5);
+            for &v in tri {
+                quadrics[v].add(&q);
+            }
+        }
+        for i in 0..3 {
+            adjacent[tri[i]].push(t);
+            let (a, b) = (tri[i], tri[(i + 1) % 3]);
+            edge_use.entry((a.min(b), a.max(b))).or_insert((0, t)).0 += 1;
+        }
+    }
+
+    // Pin open boundaries with planes perpendicular to the surface through the boundary edge
+    for (&(a, b), &(count, t)) in &edge_use {
+        if count != 1 {
+            continue;
+        }
+        let n = face_normal(&positions, &triangles[t]);
+        let edge = sub(&positions[b], &positions[a]);
+        let perp = cross(&edge, &n);
+        let len = length(&perp);
+        if len < 1e-12 {
+            continue;
+        }
+        let perp = [perp[0] / len, perp[1] / len, perp[2] / len];
+        let d = -dot(&perp, &positions[a]);
+        let q = Quadric::from_plane(perp[0], perp[1], perp[2], d, BOUNDARY_WEIGHT * length(&edge));
+        quadrics[a].add(&q);
+        quadrics[b].add(&q);
+    }
+
+    let vertex_count = positions.len();
+    let mut live_triangles = triangles.len();
+    let mut s = Simplifier {
+        positions,
+        quadrics,
+        versions: vec![0; vertex_count],
+        alive: vec![true; vertex_count],
+        tri_alive: vec![true; triangles.len()],
+        triangles,
+        adjacent,
+        heap: BinaryHeap::new(),
+    };
+    for &(a, b) in edge_use.keys() {
+        let collapse = s.plan(a, b);
+        s.heap.push(collapse);
+    }
+
+    while live_triangles > target_triangles {
+        let c = match s.heap.pop() {
+            Some(c) => c,
+            None => break,
+        };
+        if !s.alive[c.a] || !s.alive[c.b] || c.stamp != (s.versions[c.a], s.versions[c.b]) {
+            continue; // Stale
+        }
+        if s.flips(c.a, c.b, &c.position) {
+            continue;
+        }
+        live_triangles -= s.collapse(c.a, c.b, c.position);
+    }
+
+    // Compact what is left into a new mesh
+    let mut new_index = vec![u32::MAX; vertex_count];
+    let mut vertices = vec![];
+    let mut new_colors = vec![];
+    let mut indices = vec![];
+    for (t, tri) in s.triangles.iter().enumerate() {
+        if !s.tri_alive[t] {
+            continue;
+        }
+        for &v in tri {
+            if new_index[v] == u32::MAX {
+                new_index[v] = (vertices.len() / 3) as u32;
+                vertices.extend(s.positions[v].iter().map(|&x| x as f32));
+                new_colors.extend_from_slice(&colors[v]);
+            }
+            indices.push(new_index[v]);
+        }
+    }
+
+    let index_count = indices.len() as i32;
+    let mut simplified = Mesh {
+        vertices,
+        normals: vec![],
+        colors: new_colors,
+        indices,
+        index_count,
+    };
+    simplified.compute_normals();
+    simplified
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::simplify;
+    use crate::mesh::Mesh;
+
+    // The surface of a cube with `n` by `n` squares on each face, closed once the edges are welded.
+    // The coordinates are whole numbers, so the faces meet exactly.
+    fn cube(n: u32) -> Mesh {
+        let mut mesh = Mesh { vertices: vec![], normals: vec![], colors: vec![], indices: vec![], index_count: 0 };
+        for axis in 0..3 {
+            for &side in &[0, n] {
+                // The two other axes, in an order that makes the face counterclockwise from outside
+                let (u, v) = if side == n { ((axis + 1) % 3, (axis + 2) % 3) } else { ((axis + 2) % 3, (axis + 1) % 3) };
+                let first = mesh.vertices.len() as u32 / 3;
+                for j in 0..=n {
+                    for i in 0..=n {
+                        let mut p = [0.0f32; 3];
+                        p[axis] = side as f32;
+                        p[u] = i as f32;
+                        p[v] = j as f32;
+                        mesh.vertices.extend_from_slice(&p);
+                    }
+                }
+                for j in 0..n {
+                    for i in 0..n {
+                        let a = first + j * (n + 1) + i;
+                        let (b, c, d) = (a + 1, a + n + 1, a + n + 2);
+                        mesh.indices.extend_from_slice(&[a, b, d, a, d, c]);
+                    }
+                }
+            }
+        }
+        mesh.index_count = mesh.indices.len() as i32;
+        mesh
+    }
+
+    fn triangle_count(mesh: &Mesh) -> usize {
+        mesh.indices.len() / 3
+    }
+
+    // How many triangles use each edge, with the vertices welded by position
+    fn edge_use(mesh: &Mesh) -> HashMap<(usize, usize), usize> {
+        let mut welded: HashMap<[u32; 3], usize> = HashMap::new();
+        let remap: Vec<usize> = mesh.vertices.chunks_exact(3)
+            .map(|p| {
+                let next = welded.len();
+                *welded.entry([p[0].to_bits(), p[1].to_bits(), p[2].to_bits()]).or_insert(next)
+            })
+            .collect();
+        let mut edges = HashMap::new();
+        for tri in mesh.indices.chunks_exact(3) {
+            for i in 0..3 {
+                let (a, b) = (remap[tri[i] as usize], remap[tri[(i + 1) % 3] as usize]);
+                *edges.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+            }
+        }
+        edges
+    }
+
+    #[test]
+    fn reduces_to_the_target() {
+        let mesh = cube(6);
+        assert_eq!(triangle_count(&mesh), 432);
+        let simplified = simplify(&mesh, 100);
+        assert!(triangle_count(&simplified) <= 100, "{} triangles left", triangle_count(&simplified));
+        // A collapse on a closed surface removes two triangles, so it stops just below the target
+        assert!(triangle_count(&simplified) >= 98, "{} triangles left", triangle_count(&simplified));
+        assert_eq!(simplified.index_count as usize, simplified.indices.len());
+        assert_eq!(simplified.normals.len(), simplified.vertices.len());
+    }
+
+    #[test]
+    fn keeps_a_closed_mesh_closed() {
+        let simplified = simplify(&cube(6), 60);
+        let edges = edge_use(&simplified);
+        assert!(edges.values().all(|&count| count == 2), "Open or non-manifold edges after simplifying");
+        // Still a sphere topologically, V - E + F = 2
+        let vertices = simplified.vertices.len() / 3;
+        let euler = vertices as i64 - edges.len() as i64 + triangle_count(&simplified) as i64;
+        assert_eq!(euler, 2);
+    }
+
+    #[test]
+    fn is_deterministic() {
+        let mesh = cube(6);
+        let (first, second) = (simplify(&mesh, 80), simplify(&mesh, 80));
+        assert_eq!(first.indices, second.indices);
+        assert_eq!(first.vertices, second.vertices);
+    }
+
+    #[test]
+    fn lod_chain_gets_coarser() {
+        let mesh = cube(6);
+        let chain = mesh.lod_chain(3, 0.35);
+        assert_eq!(chain.len(), 3);
+        let mut previous = triangle_count(&mesh);
+        for lod in &chain {
+            let count = triangle_count(lod);
+            assert!(count > 0 && count <= (previous as f32 * 0.35) as usize, "{} after {}", count, previous);
+            previous = count;
+        }
+    }
+
+    #[test]
+    fn lod_chain_stops_when_nothing_is_left_to_remove() {
+        // A plain cube goes down to a tetrahedron, which cannot lose a triangle without folding over
+        let chain = cube(1).lod_chain(3, 0.35);
+        let counts: Vec<usize> = chain.iter().map(triangle_count).collect();
+        assert_eq!(counts, vec![4]);
+    }
+}
@@ -0,0 +1,176 @@
+// Text drawn over the finished picture with the bitmap font
+//
+// Like the debug shapes, strings are queued during the frame and drawn together at the end of it,
+// then forgotten.
+
+use crate::font::{ADVANCE, FIRST_CHAR, GLYPHS, GLYPH_HEIGHT, GLYPH_WIDTH};
+use crate::gpu::{Buffer, Texture, VertexArray};
+use crate::shader::{Shader, ShaderBuilder};
+use crate::util::{offset, size_of};
+
+// Texture unit of the font, clear of the shadow map and post-processing
+const FONT_UNIT: u32 = 4;
+
+pub const WHITE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const SHADOW: [f32; 4] = [0.0, 0.0, 0.0, 0.8];
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct TextVertex {
+    position   : [f32; 2],
+    tex_coords : [f32; 2],
+    color      : [f32; 4],
+}
+
+pub struct TextRenderer {
+    pub scale : f32,             // Screen pixels per font pixel
+    vertices  : Vec<TextVertex>, // Two triangles per character or rectangle
+    vao       : VertexArray,
+    buffer    : Buffer,
+    font      : Texture,
+    shader    : Shader,
+}
+
+// Cells of the font texture: the glyphs, then a solid one for rectangles
+const CELLS: usize = GLYPHS.len() + 1;
+
+// All cells side by side in one row, one byte of coverage per pixel
+unsafe fn font_texture() -> Texture {
+    let width = CELLS * GLYPH_WIDTH;
+    let mut pixels = vec![0u8; width * GLYPH_HEIGHT];
+    for (i, glyph) in GLYPHS.iter().enumerate() {
+        for (y, row) in glyph.iter().enumerate() {
+            for x in 0..GLYPH_WIDTH {
+                if row & (0x80 >> x) != 0 {
+                    pixels[y * width + i * GLYPH_WIDTH + x] = 255;
+                }
+            }
+        }
+    }
+    for row in pixels.chunks_exact_mut(width) {
+        row[GLYPHS.len() * GLYPH_WIDTH..].fill(255);
+    }
+
+    let texture = Texture::new(gl::TEXTURE_2D);
+    texture.bind(FONT_UNIT);
+    gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+    gl::TexImage2D(
+        gl::TEXTURE_2D,
+        0,
+        gl::R8 as i32,
+        width as i32,
+        GLYPH_HEIGHT as i32,
+        0,
+        gl::RED,
+        gl::UNSIGNED_BYTE,
+        pixels.as_ptr() as *const _,
+    );
+    gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+    texture
+}
+
+impl TextRenderer {
+    pub unsafe fn new() -> Self {
+        let vao = VertexArray::new();
+        vao.bind();
+        let buffer = Buffer::new(gl::ARRAY_BUFFER);
+        buffer.bind();
+        let stride = size_of::<TextVertex>();
+        gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, stride, offset::<f32>(0));
+        gl::EnableVertexAttribArray(0);
+        gl::VertexAttribPointer(1, 2, gl::FLOAT, gl::FALSE, stride, offset::<f32>(2));
+        gl::EnableVertexAttribArray(1);
+        gl::VertexAttribPointer(2, 4, gl::FLOAT, gl::FALSE, stride, offset::<f32>(4));
+        gl::EnableVertexAttribArray(2);
+        gl::BindVertexArray(0);
+
+        let shader = ShaderBuilder::new()
+            .attach("text.frag")
+            .attach("text.vert")
+            .link()
+            .unwrap_or_else(|log| panic!("{}", log));
+
+        TextRenderer {
+            scale: 1.0,
+            vertices: vec![],
+            vao,
+            buffer,
+            font: font_texture(),
+            shader,
+        }
+    }
+
+    pub unsafe fn reload_shader(&mut self) {
+        self.shader.reload_if_changed();
+    }
+
+    // Height of a line of text in pixels
+    pub fn line_height(&self) -> f32 {
+        (GLYPH_HEIGHT + 2) as f32 * self.scale
+    }
+
+    // Queues `text` with its top left corner at `x`, `y` pixels from the top left of the screen.
+    // Characters outside printable ASCII show as '?'.
+    pub fn text(&mut self, x: f32, y: f32, text: &str, color: [f32; 4]) {
+        // A shadow below and to the right keeps it readable over bright parts of the scene
+        self.glyphs(x + self.scale, y + self.scale, text, SHADOW);
+        self.glyphs(x, y, text, color);
+    }
+
+    // Queues a filled rectangle, `x`, `y` being its top left corner
+    pub fn rect(&mut self, x: f32, y: f32, width: f32, height: f32, color: [f32; 4]) {
+        // The middle of the solid cell, away from the glyphs next to it
+        let u = (GLYPHS.len() as f32 + 0.5) / CELLS as f32;
+        self.quad([x, y], [width, height], [u, 0.5], [0.0, 0.0], color);
+    }
+
+    fn glyphs(&mut self, x: f32, y: f32, text: &str, color: [f32; 4]) {
+        let size = [GLYPH_WIDTH as f32 * self.scale, GLYPH_HEIGHT as f32 * self.scale];
+        for (i, c) in text.chars().enumerate() {
+            let index = match c {
+                ' '..='~' => c as u8 - FIRST_CHAR,
+                _ => b'?' - FIRST_CHAR,
+            } as f32;
+            let left = x + (i * ADVANCE) as f32 * self.scale;
+            self.quad([left, y], size, [index / CELLS as f32, 0.0], [1.0 / CELLS as f32, 1.0], color);
+        }
+    }
+
+    // Two triangles covering `size` pixels from `corner`, textured with the `uv_size` part of the
+    // font texture from `uv`
+    fn quad(&mut self, corner: [f32; 2], size: [f32; 2], uv: [f32; 2], uv_size: [f32; 2], color: [f32; 4]) {
+        let vertex = |dx: f32, dy: f32| TextVertex {
+            position: [corner[0] + dx * size[0], corner[1] + dy * size[1]],
+            tex_coords: [uv[0] + dx * uv_size[0], uv[1] + dy * uv_size[1]],
+            color,
+        };
+        self.vertices.extend_from_slice(&[
+            vertex(0.0, 0.0), vertex(0.0, 1.0), vertex(1.0, 1.0),
+            vertex(0.0, 0.0), vertex(1.0, 1.0), vertex(1.0, 0.0),
+        ]);
+    }
+
+    // Draws everything queued since the last call over what is in the bound framebuffer, which is
+    // `width` by `height` pixels
+    pub unsafe fn flush(&mut self, width: i32, height: i32) {
+        if self.vertices.is_empty() {
+            return;
+        }
+        self.vao.bind();
+        self.buffer.bind();
+        self.buffer.set_data(&self.vertices, gl::STREAM_DRAW);
+        self.font.bind(FONT_UNIT);
+        self.shader.activate();
+        self.shader.set_vec2("screenSize", &glm::vec2(width as f32, height as f32));
+        gl::Disable(gl::DEPTH_TEST);
+        gl::Enable(gl::BLEND);
+        gl::DrawArrays(gl::TRIANGLES, 0, self.vertices.len() as i32);
+        gl::Disable(gl::BLEND);
+        gl::Enable(gl::DEPTH_TEST);
+        self.vertices.clear();
+    }
+}
@@ -0,0 +1,250 @@
+// Describes how the vertices of a Mesh are laid out in GPU memory
+//
+// Attributes are either kept in a buffer each, or interleaved in a single buffer so that
+// everything the vertex shader reads for a vertex sits in the same cache line. Normals and colors
+// can also be packed, a compact vertex is 12 + 4 + 4 = 20 bytes instead of 12 + 16 + 12 = 40.
+
+use crate::gpu::{Buffer, GpuMesh, VertexArray};
+use crate::mesh::Mesh;
+use crate::util::offset;
+
+// Attribute locations, matching the layout qualifiers in the shaders
+const POSITION_LOCATION: u32 = 0;
+const COLOR_LOCATION: u32 = 1;
+const NORMAL_LOCATION: u32 = 2;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum NormalFormat {
+    Float,  // Three 32-bit floats
+    Packed, // Signed normalized 10:10:10:2 in a single 32-bit integer
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ColorFormat {
+    Float, // Four 32-bit floats
+    Byte,  // Four unsigned normalized bytes
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Attribute {
+    Position,
+    Color,
+    Normal,
+}
+
+#[derive(Clone, Copy)]
+pub struct VertexLayout {
+    pub interleaved : bool, // All attributes in one buffer, or a buffer per attribute
+    pub normals     : NormalFormat,
+    pub colors      : ColorFormat,
+}
+
+impl VertexLayout {
+    // A float buffer per attribute
+    pub const SEPARATE: VertexLayout = VertexLayout {
+        interleaved: false,
+        normals: NormalFormat::Float,
+        colors: ColorFormat::Float,
+    };
+
+    pub const INTERLEAVED: VertexLayout = VertexLayout {
+        interleaved: true,
+        normals: NormalFormat::Float,
+        colors: ColorFormat::Float,
+    };
+
+    // Interleaved with packed normals and 8-bit colors
+    pub const COMPACT: VertexLayout = VertexLayout {
+        interleaved: true,
+        normals: NormalFormat::Packed,
+        colors: ColorFormat::Byte,
+    };
+
+    const ATTRIBUTES: [Attribute; 3] = [Attribute::Position, Attribute::Color, Attribute::Normal];
+
+    // The layout called `name` on the command line
+    pub fn from_name(name: &str) -> Option<VertexLayout> {
+        match name {
+            "separate" => Some(Self::SEPARATE),
+            "interleaved" => Some(Self::INTERLEAVED),
+            "compact" => Some(Self::COMPACT),
+            _ => None,
+        }
+    }
+
+    fn attribute_size(&self, attribute: Attribute) -> usize {
+        match attribute {
+            Attribute::Position => 12,
+            Attribute::Color => match self.colors {
+                ColorFormat::Float => 16,
+                ColorFormat::Byte => 4,
+            },
+            Attribute::Normal => match self.normals {
+                NormalFormat::Float => 12,
+                NormalFormat::Packed => 4,
+            },
+        }
+    }
+
+    // Size of one vertex in bytes
+    pub fn vertex_size(&self) -> usize {
+        Self::ATTRIBUTES.iter().map(|&a| self.attribute_size(a)).sum()
+    }
+
+    // Where the attribute starts within an interleaved vertex, in bytes
+    fn offset(&self, attribute: Attribute) -> usize {
+        Self::ATTRIBUTES.iter()
+            .take_while(|&&a| a != attribute)
+            .map(|&a| self.attribute_size(a))
+            .sum()
+    }
+
+    // Appends the attribute of vertex `i` to `out`. Missing data is filled in with white and zeros.
+    fn write(&self, attribute: Attribute, mesh: &Mesh, i: usize, out: &mut Vec<u8>) {
+        match attribute {
+            Attribute::Position => {
+                for k in 0..3 {
+                    out.extend_from_slice(&mesh.vertices[i * 3 + k].to_le_bytes());
+                }
+            }
+            Attribute::Color => {
+                let c = mesh.colors.get(i * 4..i * 4 + 4).unwrap_or(&[1.0; 4]);
+                match self.colors {
+                    ColorFormat::Float => c.iter().for_each(|x| out.extend_from_slice(&x.to_le_bytes())),
+                    ColorFormat::Byte => out.extend(c.iter().map(|x| (x.clamp(0.0, 1.0) * 255.0).round() as u8)),
+                }
+            }
+            Attribute::Normal => {
+                let n = mesh.normals.get(i * 3..i * 3 + 3).unwrap_or(&[0.0; 3]);
+                match self.normals {
+                    NormalFormat::Float => n.iter().for_each(|x| out.extend_from_slice(&x.to_le_bytes())),
+                    NormalFormat::Packed => out.extend_from_slice(&pack_normal(n).to_le_bytes()),
+                }
+            }
+        }
+    }
+
+    // The attribute data of every vertex, for a single interleaved buffer
+    pub fn pack(&self, mesh: &Mesh) -> Vec<u8> {
+        let num_verts = mesh.vertices.len() / 3;
+        let mut data = Vec::with_capacity(num_verts * self.vertex_size());
+        for i in 0..num_verts {
+            for &attribute in &Self::ATTRIBUTES {
+                self.write(attribute, mesh, i, &mut data);
+            }
+        }
+        data
+    }
+
+    // Configures the attribute for the currently bound array buffer
+    unsafe fn enable(&self, attribute: Attribute, stride: usize, offset_bytes: usize) {
+        let (location, size, gl_type, normalized) = match attribute {
+            Attribute::Position => (POSITION_LOCATION, 3, gl::FLOAT, gl::FALSE),
+            Attribute::Color => match self.colors {
+                ColorFormat::Float => (COLOR_LOCATION, 4, gl::FLOAT, gl::FALSE),
+                ColorFormat::Byte => (COLOR_LOCATION, 4, gl::UNSIGNED_BYTE, gl::TRUE),
+            },
+            Attribute::Normal => match self.normals {
+                NormalFormat::Float => (NORMAL_LOCATION, 3, gl::FLOAT, gl::FALSE),
+                NormalFormat::Packed => (NORMAL_LOCATION, 4, gl::INT_2_10_10_10_REV, gl::TRUE),
+            },
+        };
+        gl::VertexAttribPointer(
+            location,
+            size,
+            gl_type,
+            normalized,
+            stride as i32,
+            offset::<u8>(offset_bytes as u32),
+        );
+        gl::EnableVertexAttribArray(location);
+    }
+
+    // Creates a VAO holding the mesh
+    pub unsafe fn upload(&self, mesh: &Mesh) -> GpuMesh {
+        let vao = VertexArray::new();
+        vao.bind();
+
+        let mut buffers = vec![];
+        let num_verts = mesh.vertices.len() / 3;
+        if self.interleaved {
+            let data = self.pack(mesh);
+            buffers.push(Buffer::with_data(gl::ARRAY_BUFFER, &data, gl::STATIC_DRAW));
+            for &attribute in &Self::ATTRIBUTES {
+                self.enable(attribute, self.vertex_size(), self.offset(attribute));
+            }
+        } else {
+            for &attribute in &Self::ATTRIBUTES {
+                let mut data = Vec::with_capacity(num_verts * self.attribute_size(attribute));
+                for i in 0..num_verts {
+                    self.write(attribute, mesh, i, &mut data);
+                }
+                buffers.push(Buffer::with_data(gl::ARRAY_BUFFER, &data, gl::STATIC_DRAW));
+                self.enable(attribute, self.attribute_size(attribute), 0);
+            }
+        }
+
+        buffers.push(Buffer::with_data(gl::ELEMENT_ARRAY_BUFFER, &mesh.indices, gl::STATIC_DRAW));
+
+        gl::BindVertexArray(0);
+        GpuMesh::new(vao, buffers, mesh.index_count, mesh.is_transparent())
+    }
+}
+
+// Signed normalized 10:10:10:2, x in the lowest bits
+fn pack_normal(n: &[f32]) -> u32 {
+    let component = |x: f32| ((x.clamp(-1.0, 1.0) * 511.0).round() as i32 as u32) & 0x3ff;
+    component(n[0]) | component(n[1]) << 10 | component(n[2]) << 20
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The three 10-bit components as signed integers
+    fn unpack(packed: u32) -> [i32; 3] {
+        [0, 10, 20].map(|shift| ((packed >> shift & 0x3ff) as i32) << 22 >> 22)
+    }
+
+    #[test]
+    fn pack_normal_puts_x_lowest() {
+        assert_eq!(pack_normal(&[1.0, 0.0, 0.0]), 511);
+        assert_eq!(pack_normal(&[0.0, 1.0, 0.0]), 511 << 10);
+        assert_eq!(pack_normal(&[0.0, 0.0, 1.0]), 511 << 20);
+        // The two highest bits stay clear
+        assert_eq!(pack_normal(&[-1.0, -1.0, -1.0]) >> 30, 0);
+    }
+
+    #[test]
+    fn pack_normal_keeps_signs_and_clamps() {
+        assert_eq!(unpack(pack_normal(&[-1.0, 0.0, 1.0])), [-511, 0, 511]);
+        assert_eq!(unpack(pack_normal(&[0.5, -0.5, -0.25])), [256, -256, -128]);
+        assert_eq!(unpack(pack_normal(&[2.0, -3.0, 0.0])), [511, -511, 0]);
+    }
+
+    #[test]
+    fn compact_vertices_are_20_bytes() {
+        let layout = VertexLayout::COMPACT;
+        assert_eq!(layout.vertex_size(), 20);
+        assert_eq!(layout.offset(Attribute::Position), 0);
+        assert_eq!(layout.offset(Attribute::Color), 12);
+        assert_eq!(layout.offset(Attribute::Normal), 16);
+        assert_eq!(VertexLayout::INTERLEAVED.vertex_size(), 40);
+    }
+
+    #[test]
+    fn compact_packing_rounds_colors_to_bytes() {
+        let mesh = Mesh {
+            vertices: vec![1.0, 2.0, 3.0],
+            normals: vec![0.0, -1.0, 0.0],
+            colors: vec![0.5, 1.5, -0.2, 0.999],
+            indices: vec![],
+            index_count: 0,
+        };
+        let data = VertexLayout::COMPACT.pack(&mesh);
+        assert_eq!(data.len(), 20);
+        assert_eq!(&data[0..12], [1.0f32, 2.0, 3.0].map(f32::to_le_bytes).concat());
+        assert_eq!(&data[12..16], [128, 255, 0, 255]);
+        assert_eq!(&data[16..20], pack_normal(&[0.0, -1.0, 0.0]).to_le_bytes());
+    }
+}